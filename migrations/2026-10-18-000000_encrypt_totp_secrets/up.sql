@@ -0,0 +1,3 @@
+-- айди ключа, которым зашифрован totp_secret
+-- NULL при заполненном totp_secret - секрет ещё не зашифрован
+ALTER TABLE users ADD COLUMN totp_key_id TEXT;
@@ -0,0 +1,3 @@
+ALTER TABLE sessions DROP COLUMN compromised;
+ALTER TABLE sessions DROP COLUMN refresh_generation;
+ALTER TABLE sessions DROP COLUMN family_id;
@@ -0,0 +1,8 @@
+-- каждая сессия - отдельное семейство refresh токенов
+ALTER TABLE sessions ADD COLUMN family_id TEXT NOT NULL DEFAULT gen_random_uuid()::text;
+ALTER TABLE sessions ALTER COLUMN family_id DROP DEFAULT;
+ALTER TABLE sessions ADD CONSTRAINT sessions_family_id_key UNIQUE (family_id);
+-- номер текущего refresh токена в семействе
+ALTER TABLE sessions ADD COLUMN refresh_generation INT4 NOT NULL DEFAULT 0;
+-- сессия была завершена из-за повторного использования refresh токена
+ALTER TABLE sessions ADD COLUMN compromised BOOLEAN NOT NULL DEFAULT FALSE;
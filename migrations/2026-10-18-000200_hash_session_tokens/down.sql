@@ -0,0 +1,3 @@
+ALTER TABLE sessions ADD COLUMN refresh_token TEXT NOT NULL DEFAULT '';
+ALTER TABLE sessions ADD COLUMN jwt TEXT NOT NULL DEFAULT '';
+ALTER TABLE sessions DROP COLUMN refresh_token_hash;
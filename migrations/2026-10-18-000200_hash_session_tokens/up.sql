@@ -0,0 +1,7 @@
+-- в бд храним только HMAC от refresh токена, access токены не храним вообще
+ALTER TABLE sessions ADD COLUMN refresh_token_hash TEXT NOT NULL DEFAULT '';
+ALTER TABLE sessions ALTER COLUMN refresh_token_hash DROP DEFAULT;
+-- старые токены не содержат айди сессии (sid), так что найти по ним сессию уже нельзя
+UPDATE sessions SET is_active = FALSE WHERE is_active;
+ALTER TABLE sessions DROP COLUMN jwt;
+ALTER TABLE sessions DROP COLUMN refresh_token;
@@ -0,0 +1,5 @@
+-- сессия с "запомнить меня" получает более долгий refresh токен
+ALTER TABLE sessions ADD COLUMN remember BOOLEAN NOT NULL DEFAULT FALSE;
+-- от него считается максимальный возраст сессии
+ALTER TABLE sessions ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT now();
+UPDATE sessions SET created_at = last_activity;
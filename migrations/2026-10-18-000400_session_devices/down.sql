@@ -0,0 +1,2 @@
+DROP INDEX sessions_user_device_idx;
+ALTER TABLE sessions DROP COLUMN device_id;
@@ -0,0 +1,3 @@
+-- айди установки клиента, по нему (а не по User-Agent) повторный вход заменяет старую сессию
+ALTER TABLE sessions ADD COLUMN device_id TEXT;
+CREATE INDEX sessions_user_device_idx ON sessions (user_id, device_id) WHERE is_active;
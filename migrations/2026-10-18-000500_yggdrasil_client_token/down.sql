@@ -0,0 +1,2 @@
+DROP INDEX sessions_user_client_token_idx;
+ALTER TABLE sessions DROP COLUMN client_token;
@@ -0,0 +1,3 @@
+-- clientToken лаунчера (Yggdrasil), есть только у сессий, созданных через /authserver
+ALTER TABLE sessions ADD COLUMN client_token TEXT;
+CREATE INDEX sessions_user_client_token_idx ON sessions (user_id, client_token) WHERE is_active;
@@ -0,0 +1,21 @@
+-- Minecraft UUID аккаунта (32 hex символа без дефисов)
+ALTER TABLE users ADD COLUMN uuid TEXT;
+
+-- существующим аккаунтам выдаём офлайн UUID (v3 от ``OfflinePlayer:<ник>``),
+-- чтобы они совпадали с UUID игроков в мирах офлайн-серверов
+UPDATE users
+SET uuid = encode(
+  set_byte(
+    set_byte(hash, 6, (get_byte(hash, 6) & 15) | 48),
+    8, (get_byte(hash, 8) & 63) | 128
+  ),
+  'hex'
+)
+FROM (
+  SELECT id AS hash_id, decode(md5('OfflinePlayer:' || username), 'hex') AS hash
+  FROM users
+) AS hashes
+WHERE users.id = hashes.hash_id;
+
+ALTER TABLE users ALTER COLUMN uuid SET NOT NULL;
+CREATE UNIQUE INDEX users_uuid_idx ON users (uuid);
@@ -1,7 +1,7 @@
-use axum::{extract::State, http::HeaderMap, routing::post, Json};
-use adjust::{controller::Controller, response::HttpResult};
+use axum::{extract::State, http::{HeaderMap, StatusCode}, routing::{get, post}, Json};
+use adjust::{controller::Controller, response::{HttpError, HttpMessage, HttpResult}};
 use serde::{Deserialize, Serialize};
-use crate::{misc::UserAgent, models::{BaseUserInfo, Session, UserLogin}, service::auth::AuthService, AppState};
+use crate::{extractor::{AuthenticatedUser, IntrospectionClient}, misc::{BearerToken, Cookies, UserAgent}, models::{BaseUserInfo, SessionWithTokens, UserLogin}, service::{auth::AuthService, session::SessionService}, AppState};
 
 pub struct AuthController;
 
@@ -10,11 +10,23 @@ pub struct RefreshToken {
   refresh_jwt: String
 }
 
+lazy_static::lazy_static! {
+  // кука, из которой /verify берёт токен, если нет заголовка Authorization
+  static ref AUTH_COOKIE: String = std::env::var("AUTH_COOKIE")
+    .unwrap_or(String::from("access_token"));
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct JsonWebToken {
   pub token: String
 }
 
+#[derive(Deserialize)]
+pub struct OwnerInvalidate {
+  /// Айди пользователя в сервисе user
+  user_id: i32
+}
+
 impl AuthController {
   pub async fn login(
     headers: HeaderMap,
@@ -29,22 +41,60 @@ impl AuthController {
       .await
   }
 
+  pub async fn logout(
+    State(state): State<AppState>,
+    user: AuthenticatedUser
+  ) -> HttpResult<HttpMessage> {
+    let mut db = state.postgres.get()?;
+    let mut redis = state.redis.get()?;
+
+    SessionService::logout(&mut db, &mut redis, &user.session, &user.claims)
+  }
+
   pub async fn get_token_owner(
     State(state): State<AppState>,
     Json(body): Json<JsonWebToken>
   ) -> HttpResult<BaseUserInfo> {
     let mut db = state.postgres.get()?;
+    let mut redis = state.redis.get()?;
 
-    AuthService::get_owner(&mut db, body.token)
+    AuthService::get_owner(&mut db, &mut redis, body.token)
+  }
+
+  pub async fn verify(
+    headers: HeaderMap,
+    State(state): State<AppState>
+  ) -> Result<(StatusCode, HeaderMap), HttpError> {
+    let token = headers.get_bearer_token()
+      .or_else(|err| headers.get_cookie(&AUTH_COOKIE).ok_or(err))?;
+
+    let mut db = state.postgres.get()?;
+    let mut redis = state.redis.get()?;
+
+    let headers = AuthService::verify(&mut db, &mut redis, &token)?;
+
+    Ok((StatusCode::OK, headers))
+  }
+
+  pub async fn invalidate_owner(
+    State(state): State<AppState>,
+    _client: IntrospectionClient,
+    Json(body): Json<OwnerInvalidate>
+  ) -> HttpResult<HttpMessage> {
+    let mut redis = state.redis.get()?;
+
+    AuthService::invalidate_owner(&mut redis, body.user_id)
   }
 
   pub async fn refresh(
+    headers: HeaderMap,
     State(state): State<AppState>,
     Json(body): Json<RefreshToken>,
-  ) -> HttpResult<Session> {
+  ) -> HttpResult<SessionWithTokens> {
     let mut db = state.postgres.get()?;
+    let mut redis = state.redis.get()?;
 
-    AuthService::refresh(&mut db, body.refresh_jwt)
+    AuthService::refresh(&mut db, &mut redis, body.refresh_jwt, headers.get_bearer_token().ok())
       .await
   }
 }
@@ -57,7 +107,10 @@ impl Controller<AppState> for AuthController {
   fn register(&self, router: axum::Router<AppState>) -> axum::Router<AppState> {
     router
       .route("/login", post(Self::login)) // логин
+      .route("/logout", post(Self::logout)) // выход из аккаунта
       .route("/refresh", post(Self::refresh)) // обновление токена
       .route("/owner", post(Self::get_token_owner)) // возвращает владельца токена
+      .route("/verify", get(Self::verify)) // forward-auth для nginx/Traefik
+      .route("/owner/invalidate", post(Self::invalidate_owner)) // сбрасывает кэш /owner пользователя
   }
 }
\ No newline at end of file
@@ -0,0 +1,39 @@
+use axum::{extract::State, routing::post, Form};
+use adjust::{controller::Controller, response::HttpResult};
+use serde::Deserialize;
+use crate::{extractor::IntrospectionClient, service::introspection::{IntrospectionResponse, IntrospectionService}, AppState};
+
+pub struct IntrospectionController;
+
+#[derive(Deserialize)]
+pub struct IntrospectionBody {
+  token: String,
+  /// Подсказка о типе токена (RFC 7662), мы определяем тип сами
+  #[allow(dead_code)]
+  token_type_hint: Option<String>
+}
+
+impl IntrospectionController {
+  /// Проверка токена для внутренних сервисов (RFC 7662)
+  pub async fn introspect(
+    State(state): State<AppState>,
+    _client: IntrospectionClient,
+    Form(body): Form<IntrospectionBody>
+  ) -> HttpResult<IntrospectionResponse> {
+    let mut db = state.postgres.get()?;
+    let mut redis = state.redis.get()?;
+
+    IntrospectionService::introspect(&mut db, &mut redis, &body.token)
+  }
+}
+
+impl Controller<AppState> for IntrospectionController {
+  fn new() -> anyhow::Result<Box<Self>> {
+    Ok(Box::new(Self))
+  }
+
+  fn register(&self, router: axum::Router<AppState>) -> axum::Router<AppState> {
+    router
+      .route("/introspect", post(Self::introspect))
+  }
+}
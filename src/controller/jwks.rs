@@ -0,0 +1,24 @@
+use adjust::controller::Controller;
+use axum::{routing::get, Json, Router};
+use jsonwebtoken::jwk::JwkSet;
+use crate::{service::keystore::KeyStore, AppState};
+
+pub struct JwksController;
+
+impl JwksController {
+  /// Публичные ключи для офлайн-проверки JWT
+  async fn jwks() -> Json<JwkSet> {
+    Json(KeyStore::get().jwks())
+  }
+}
+
+impl Controller<AppState> for JwksController {
+  fn new() -> anyhow::Result<Box<Self>> {
+    Ok(Box::new(Self))
+  }
+
+  fn register(&self, router: Router<AppState>) -> Router<AppState> {
+    router
+      .route("/.well-known/jwks.json", get(Self::jwks))
+  }
+}
\ No newline at end of file
@@ -1,5 +1,8 @@
 pub mod auth;
+pub mod introspection;
+pub mod jwks;
 pub mod recovery;
 pub mod register;
 pub mod tfa;
-pub mod sessions;
\ No newline at end of file
+pub mod sessions;
+pub mod yggdrasil;
\ No newline at end of file
@@ -1,18 +1,53 @@
-use adjust::{controller::Controller, response::HttpResult};
-use axum::{extract::{Path, State}, routing::get, Json, Router};
-use crate::{models::SessionSafe, repository::session::SessionRepository, AppState};
+use adjust::{controller::Controller, response::{HttpMessage, HttpResult}};
+use axum::{extract::{Path, State}, routing::{delete, get, post}, Json, Router};
+use crate::{extractor::{AdminUser, AuthenticatedUser}, models::SessionSafe, repository::session::SessionRepository, service::session::SessionService, AppState};
 
 pub struct SessionsController;
 
 impl SessionsController {
+  /// Активные сессии текущего пользователя
   async fn get_sessions(
     State(state): State<AppState>,
+    user: AuthenticatedUser
+  ) -> HttpResult<Vec<SessionSafe>> {
+    let mut db = state.postgres.get()?;
+
+    Ok(Json(SessionRepository::get_sessions(&mut db, user.global_id())?))
+  }
+
+  /// Активные сессии любого пользователя (только для админов)
+  async fn get_user_sessions(
+    State(state): State<AppState>,
+    _: AdminUser,
     Path(user_id): Path<i32>
   ) -> HttpResult<Vec<SessionSafe>> {
     let mut db = state.postgres.get()?;
 
     Ok(Json(SessionRepository::get_sessions(&mut db, user_id)?))
   }
+
+  /// Завершает одну из сессий текущего пользователя
+  async fn revoke(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Path(session_id): Path<i32>
+  ) -> HttpResult<HttpMessage> {
+    let mut db = state.postgres.get()?;
+    let mut redis = state.redis.get()?;
+
+    SessionService::revoke(&mut db, &mut redis, &user.session, session_id)
+  }
+
+  /// Завершает все сессии текущего пользователя, кроме текущей
+  async fn revoke_others(
+    State(state): State<AppState>,
+    user: AuthenticatedUser
+  ) -> HttpResult<HttpMessage> {
+    let mut db = state.postgres.get()?;
+    let mut redis = state.redis.get()?;
+
+    SessionService::revoke_others(&mut db, &mut redis, &user.session)
+  }
 }
 
 impl Controller<AppState> for SessionsController {
@@ -24,7 +59,10 @@ impl Controller<AppState> for SessionsController {
     router
       .nest("/sessions",
         Router::new()
-          .route("/{id}", get(Self::get_sessions))
+          .route("/", get(Self::get_sessions))
+          .route("/user/{user_id}", get(Self::get_user_sessions))
+          .route("/{session_id}", delete(Self::revoke))
+          .route("/revoke-others", post(Self::revoke_others))
       )
   }
 }
\ No newline at end of file
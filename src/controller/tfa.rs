@@ -1,7 +1,7 @@
 use adjust::{controller::Controller, response::{HttpMessage, HttpResult}};
-use axum::{extract::{Path, Query, State}, http::HeaderMap, routing::post, Json, Router};
+use axum::{extract::{Path, State}, http::HeaderMap, routing::post, Json, Router};
 use serde::{Deserialize, Serialize};
-use crate::{misc::UserAgent, models::Session, service::logic::tfa::TFAService, AppState};
+use crate::{extractor::{AdminUser, AuthenticatedUser}, misc::UserAgent, service::logic::tfa::{BackupCodesResponse, TFALoginResponse, TFAService}, AppState};
 
 #[derive(Deserialize, Serialize)]
 pub struct TFAAddBody {
@@ -10,19 +10,25 @@ pub struct TFAAddBody {
 }
 
 #[derive(Deserialize)]
-pub struct TFAQuery {
-  username: String,
+pub struct TFALoginBody {
+  ticket: String,
+  code: String
 }
 
 #[derive(Deserialize)]
-pub struct TFALoginBody {
+pub struct TFACodeBody {
+  code: String
+}
+
+#[derive(Deserialize)]
+pub struct TFADisableBody {
+  password: String,
   code: String
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct TFALinkBody {
-  code: String,
-  secret: String
+  code: String
 }
 
 pub struct TFAController;
@@ -31,36 +37,100 @@ impl TFAController {
   /// Генерирует 2FA Secret и отправляет его в виде Json'а
   async fn add(
     State(state): State<AppState>,
+    user: AuthenticatedUser
+  ) -> HttpResult<TFAAddBody> {
+    let mut db = state.postgres.get()?;
+    let mut redis = state.redis.get()?;
+
+    TFAService::add(&mut db, &mut redis, user.user_id())
+  }
+
+  /// Генерирует 2FA Secret для указанного пользователя (только для админов)
+  async fn add_for(
+    State(state): State<AppState>,
+    _: AdminUser,
     Path(id): Path<i32>
   ) -> HttpResult<TFAAddBody> {
     let mut db = state.postgres.get()?;
+    let mut redis = state.redis.get()?;
 
-    TFAService::add(&mut db, id)
+    TFAService::add(&mut db, &mut redis, id)
   }
 
   /// Привязывает 2FA Secret к профилю
   async fn link(
     State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Json(body): Json<TFALinkBody>
+  ) -> HttpResult<BackupCodesResponse> {
+    let mut db = state.postgres.get()?;
+    let mut redis = state.redis.get()?;
+
+    TFAService::link(&mut db, &mut redis, user.user_id(), body.code)
+  }
+
+  /// Привязывает 2FA Secret к профилю указанного пользователя (только для админов)
+  async fn link_for(
+    State(state): State<AppState>,
+    _: AdminUser,
     Path(id): Path<i32>,
     Json(body): Json<TFALinkBody>
+  ) -> HttpResult<BackupCodesResponse> {
+    let mut db = state.postgres.get()?;
+    let mut redis = state.redis.get()?;
+
+    TFAService::link(&mut db, &mut redis, id, body.code)
+  }
+
+  /// Перевыпускает резервные коды
+  async fn regenerate_backup_codes(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Json(body): Json<TFACodeBody>
+  ) -> HttpResult<BackupCodesResponse> {
+    let mut db = state.postgres.get()?;
+    let mut redis = state.redis.get()?;
+
+    TFAService::regenerate_backup_codes(&mut db, &mut redis, user.user_id(), body.code)
+  }
+
+  /// Отключает 2FA (нужен пароль и TOTP/резервный код)
+  async fn disable(
+    State(state): State<AppState>,
+    user: AuthenticatedUser,
+    Json(body): Json<TFADisableBody>
   ) -> HttpResult<HttpMessage> {
     let mut db = state.postgres.get()?;
+    let mut redis = state.redis.get()?;
+
+    TFAService::disable(&mut db, &mut redis, user.user_id(), body.password, body.code)
+      .await
+  }
 
-    TFAService::link(&mut db, id, body.code, body.secret)
+  /// Сбрасывает 2FA у пользователя и завершает все его сессии (только для админов)
+  async fn reset(
+    State(state): State<AppState>,
+    _: AdminUser,
+    Path(id): Path<i32>
+  ) -> HttpResult<HttpMessage> {
+    let mut db = state.postgres.get()?;
+    let mut redis = state.redis.get()?;
+
+    TFAService::reset(&mut db, &mut redis, id)
+      .await
   }
 
   /// Входит в аккаунт
   async fn login(
     headers: HeaderMap,
     State(state): State<AppState>,
-    Query(params): Query<TFAQuery>,
     Json(body): Json<TFALoginBody>
-  ) -> HttpResult<Session> {
+  ) -> HttpResult<TFALoginResponse> {
     let user_agent = headers.get_user_agent();
     let mut redis = state.redis.get()?;
     let mut db = state.postgres.get()?;
 
-    TFAService::login(&mut db, &mut redis, params.username, body.code, user_agent)
+    TFAService::login(&mut db, &mut redis, body.ticket, body.code, user_agent)
       .await
   }
 }
@@ -74,8 +144,13 @@ impl Controller<AppState> for TFAController {
     router
       .nest("/2fa",
         Router::new()
-          .route("/add/{id}", post(Self::add))
-          .route("/link/{id}", post(Self::link))
+          .route("/add", post(Self::add))
+          .route("/add/{id}", post(Self::add_for))
+          .route("/link", post(Self::link))
+          .route("/link/{id}", post(Self::link_for))
+          .route("/backup-codes", post(Self::regenerate_backup_codes))
+          .route("/disable", post(Self::disable))
+          .route("/reset/{id}", post(Self::reset))
           .route("/login", post(Self::login))
       )
   }
@@ -0,0 +1,229 @@
+use std::net::SocketAddr;
+use axum::{extract::{ConnectInfo, Path, Query, State}, http::{HeaderMap, StatusCode}, response::Response, routing::{get, post}, Json, Router};
+use adjust::controller::Controller;
+use serde::Deserialize;
+use crate::{misc::{BearerToken, ClientIp, UserAgent}, service::logic::{certificates::{CertificatesService, PlayerCertificates}, yggdrasil::{ApiMetadata, AuthenticateResponse, GameProfile, RefreshResponse, YggdrasilError, YggdrasilResult, YggdrasilService}}, AppState};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticateBody {
+  username: String,
+  password: String,
+  client_token: Option<String>,
+  #[serde(default)]
+  request_user: bool
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RefreshBody {
+  access_token: String,
+  client_token: Option<String>,
+  #[serde(default)]
+  request_user: bool,
+  selected_profile: Option<GameProfile>
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenBody {
+  access_token: String,
+  client_token: Option<String>
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JoinBody {
+  access_token: String,
+  selected_profile: String,
+  server_id: String
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HasJoinedQuery {
+  username: String,
+  server_id: String,
+  ip: Option<String>
+}
+
+#[derive(Deserialize)]
+pub struct ProfileQuery {
+  // по умолчанию свойства не подписываются
+  unsigned: Option<bool>
+}
+
+#[derive(Deserialize)]
+pub struct SignoutBody {
+  username: String,
+  password: String
+}
+
+/// Yggdrasil authserver (совместим с authlib-injector)
+pub struct YggdrasilController;
+
+impl YggdrasilController {
+  /// Метаданные API для authlib-injector
+  async fn metadata() -> Json<ApiMetadata> {
+    YggdrasilService::metadata()
+  }
+
+  /// Вход из лаунчера
+  async fn authenticate(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Json(body): Json<AuthenticateBody>
+  ) -> YggdrasilResult<AuthenticateResponse> {
+    let user_agent = headers.get_user_agent();
+    let mut db = state.postgres.get().map_err(YggdrasilError::internal)?;
+    let mut redis = state.redis.get().map_err(YggdrasilError::internal)?;
+
+    YggdrasilService::authenticate(&mut db, &mut redis, body.username, body.password, body.client_token, body.request_user, &user_agent)
+  }
+
+  /// Обновление accessToken
+  async fn refresh(
+    State(state): State<AppState>,
+    Json(body): Json<RefreshBody>
+  ) -> YggdrasilResult<RefreshResponse> {
+    let mut db = state.postgres.get().map_err(YggdrasilError::internal)?;
+    let mut redis = state.redis.get().map_err(YggdrasilError::internal)?;
+
+    YggdrasilService::refresh(&mut db, &mut redis, body.access_token, body.client_token, body.request_user, body.selected_profile)
+  }
+
+  /// Проверка accessToken
+  async fn validate(
+    State(state): State<AppState>,
+    Json(body): Json<TokenBody>
+  ) -> Result<StatusCode, YggdrasilError> {
+    let mut db = state.postgres.get().map_err(YggdrasilError::internal)?;
+    let mut redis = state.redis.get().map_err(YggdrasilError::internal)?;
+
+    YggdrasilService::validate(&mut db, &mut redis, &body.access_token, body.client_token.as_deref())
+  }
+
+  /// Завершение сессии по accessToken
+  async fn invalidate(
+    State(state): State<AppState>,
+    Json(body): Json<TokenBody>
+  ) -> Result<StatusCode, YggdrasilError> {
+    let mut db = state.postgres.get().map_err(YggdrasilError::internal)?;
+    let mut redis = state.redis.get().map_err(YggdrasilError::internal)?;
+
+    YggdrasilService::invalidate(&mut db, &mut redis, &body.access_token, body.client_token.as_deref())
+  }
+
+  /// Завершение всех сессий лаунчеров по нику и паролю
+  async fn signout(
+    State(state): State<AppState>,
+    Json(body): Json<SignoutBody>
+  ) -> Result<StatusCode, YggdrasilError> {
+    let mut db = state.postgres.get().map_err(YggdrasilError::internal)?;
+    let mut redis = state.redis.get().map_err(YggdrasilError::internal)?;
+
+    YggdrasilService::signout(&mut db, &mut redis, body.username, body.password)
+  }
+
+  /// Клиент входит на сервер
+  async fn join(
+    headers: HeaderMap,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    State(state): State<AppState>,
+    Json(body): Json<JoinBody>
+  ) -> Result<StatusCode, YggdrasilError> {
+    let mut db = state.postgres.get().map_err(YggdrasilError::internal)?;
+    let mut redis = state.redis.get().map_err(YggdrasilError::internal)?;
+
+    YggdrasilService::join(&mut db, &mut redis, &body.access_token, &body.selected_profile, body.server_id, headers.get_client_ip(peer.ip()).to_string())
+  }
+
+  /// Сервер проверяет вход игрока
+  async fn has_joined(
+    State(state): State<AppState>,
+    Query(query): Query<HasJoinedQuery>
+  ) -> Result<Response, YggdrasilError> {
+    let mut redis = state.redis.get().map_err(YggdrasilError::internal)?;
+
+    YggdrasilService::has_joined(&mut redis, &query.username, &query.server_id, query.ip.as_deref())
+  }
+
+  /// Ключи игрока для подписи чата
+  async fn certificates(
+    headers: HeaderMap,
+    State(state): State<AppState>
+  ) -> YggdrasilResult<PlayerCertificates> {
+    let token = headers.get_bearer_token()
+      .map_err(|_| YggdrasilError::invalid_token())?;
+
+    let mut db = state.postgres.get().map_err(YggdrasilError::internal)?;
+    let mut redis = state.redis.get().map_err(YggdrasilError::internal)?;
+
+    CertificatesService::get(&mut db, &mut redis, &token)
+      .await
+  }
+
+  /// Профиль игрока по нику
+  async fn profile_by_name(
+    State(state): State<AppState>,
+    Path(username): Path<String>
+  ) -> Result<Response, YggdrasilError> {
+    let mut db = state.postgres.get().map_err(YggdrasilError::internal)?;
+
+    YggdrasilService::profile_by_name(&mut db, &username)
+  }
+
+  /// Профили игроков по списку ников
+  async fn profiles_by_names(
+    State(state): State<AppState>,
+    Json(usernames): Json<Vec<String>>
+  ) -> YggdrasilResult<Vec<GameProfile>> {
+    let mut db = state.postgres.get().map_err(YggdrasilError::internal)?;
+
+    YggdrasilService::profiles_by_names(&mut db, usernames)
+  }
+
+  /// Профиль игрока по UUID
+  async fn profile(
+    State(state): State<AppState>,
+    Path(uuid): Path<String>,
+    Query(query): Query<ProfileQuery>
+  ) -> Result<Response, YggdrasilError> {
+    let mut db = state.postgres.get().map_err(YggdrasilError::internal)?;
+
+    YggdrasilService::profile_by_uuid(&mut db, &uuid, !query.unsigned.unwrap_or(true))
+  }
+}
+
+impl Controller<AppState> for YggdrasilController {
+  fn new() -> anyhow::Result<Box<Self>> {
+    Ok(Box::new(Self))
+  }
+
+  fn register(&self, router: Router<AppState>) -> Router<AppState> {
+    router
+      .route("/", get(Self::metadata))
+      .nest("/authserver",
+        Router::new()
+          .route("/authenticate", post(Self::authenticate))
+          .route("/refresh", post(Self::refresh))
+          .route("/validate", post(Self::validate))
+          .route("/invalidate", post(Self::invalidate))
+          .route("/signout", post(Self::signout))
+      )
+      .nest("/sessionserver/session/minecraft",
+        Router::new()
+          .route("/join", post(Self::join))
+          .route("/hasJoined", get(Self::has_joined))
+          .route("/profile/{uuid}", get(Self::profile))
+      )
+      .route("/player/certificates", post(Self::certificates))
+      // authlib-injector проксирует сюда api.minecraftservices.com
+      .route("/minecraftservices/player/certificates", post(Self::certificates))
+      .nest("/api",
+        Router::new()
+          .route("/profiles/minecraft", post(Self::profiles_by_names))
+          .route("/users/profiles/minecraft/{username}", get(Self::profile_by_name))
+      )
+  }
+}
@@ -0,0 +1,119 @@
+//! Экстракторы axum для авторизованных эндпоинтов
+
+#![allow(dead_code)]
+
+use std::env;
+use adjust::response::HttpError;
+use axum::{extract::FromRequestParts, http::{request::Parts, StatusCode}, Json};
+use crate::{misc::{BasicCredentials, BearerToken}, models::Session, repository::user::UserRepository, service::{hasher::HasherService, jwt::Claims, session::SessionService}, AppState};
+
+lazy_static::lazy_static! {
+  // ранги из сервиса user, которым можно работать с чужими аккаунтами
+  static ref ADMIN_RANKS: Vec<String> = env::var("ADMIN_RANKS")
+    .unwrap_or(String::from("admin"))
+    .split(',')
+    .map(|rank| rank.trim().to_owned())
+    .collect();
+  // внутренние сервисы, которым можно проверять токены через /introspect
+  // в формате ``client_id:secret,client_id:secret``
+  static ref INTROSPECTION_CLIENTS: Vec<(String, String)> = env::var("INTROSPECTION_CLIENTS")
+    .unwrap_or_default()
+    .split(',')
+    .filter_map(|pair| pair.trim().split_once(':'))
+    .map(|(client_id, secret)| (client_id.to_owned(), secret.to_owned()))
+    .collect();
+}
+
+/// Пользователь, авторизованный через ``Authorization: Bearer <JWT>``
+pub struct AuthenticatedUser {
+  pub session: Session,
+  /// Claims access токена, с которым пришёл запрос
+  pub claims: Claims
+}
+
+impl AuthenticatedUser {
+  /// Айди пользователя в нашей бд (``users.id``)
+  pub fn user_id(&self) -> i32 {
+    self.session.user_id
+  }
+
+  /// Айди пользователя из сервиса user
+  pub fn global_id(&self) -> i32 {
+    self.session.global_id
+  }
+}
+
+impl FromRequestParts<AppState> for AuthenticatedUser {
+  type Rejection = HttpError;
+
+  async fn from_request_parts(
+    parts: &mut Parts,
+    state: &AppState
+  ) -> Result<Self, Self::Rejection> {
+    let token = parts.headers.get_bearer_token()?;
+    let mut db = state.postgres.get()?;
+    let mut redis = state.redis.get()?;
+
+    let unauthorized = || HttpError::new("Невалидный или истёкший токен", Some(StatusCode::UNAUTHORIZED));
+
+    let Json(claims) = SessionService::verify_access(&mut redis, &token)
+      .map_err(|_| unauthorized())?;
+    let Json(session) = SessionService::get_by_claims(&mut db, &mut redis, &claims, true)
+      .map_err(|_| unauthorized())?;
+
+    Ok(AuthenticatedUser { session, claims })
+  }
+}
+
+/// Пользователь с админским рангом (см. ``ADMIN_RANKS``)
+///
+/// Только ему можно передавать чужой айди в пути запроса.
+pub struct AdminUser(pub AuthenticatedUser);
+
+impl FromRequestParts<AppState> for AdminUser {
+  type Rejection = HttpError;
+
+  async fn from_request_parts(
+    parts: &mut Parts,
+    state: &AppState
+  ) -> Result<Self, Self::Rejection> {
+    let user = AuthenticatedUser::from_request_parts(parts, state)
+      .await?;
+
+    let info = UserRepository::find(user.global_id())
+      .await
+      .map_err(|_| HttpError::new("Пользователь не был найден", Some(StatusCode::FORBIDDEN)))?;
+
+    if !ADMIN_RANKS.contains(&info.rank) {
+      return Err(HttpError::new("Недостаточно прав", Some(StatusCode::FORBIDDEN)));
+    }
+
+    Ok(AdminUser(user))
+  }
+}
+
+/// Внутренний сервис, авторизованный через ``Authorization: Basic``
+/// (см. ``INTROSPECTION_CLIENTS``)
+pub struct IntrospectionClient {
+  pub client_id: String
+}
+
+impl FromRequestParts<AppState> for IntrospectionClient {
+  type Rejection = HttpError;
+
+  async fn from_request_parts(
+    parts: &mut Parts,
+    _state: &AppState
+  ) -> Result<Self, Self::Rejection> {
+    let (client_id, secret) = parts.headers.get_basic_credentials()?;
+
+    let known = INTROSPECTION_CLIENTS.iter()
+      .any(|(id, expected)| id == &client_id && HasherService::secure_eq(&secret, expected));
+
+    if !known {
+      return Err(HttpError::new("Неверные данные клиента", Some(StatusCode::UNAUTHORIZED)));
+    }
+
+    Ok(IntrospectionClient { client_id })
+  }
+}
\ No newline at end of file
@@ -1,9 +1,13 @@
-use std::sync::Arc;
-use controller::{auth::AuthController, recovery::RecoveryController, register::RegisterController, sessions::SessionsController, tfa::TFAController};
-use adjust::{main, controllers, database::{postgres::Postgres, redis::Redis, Pool}, controller::Controller, service::Service};
+use std::{net::SocketAddr, sync::Arc};
+use axum::Router;
+use controller::{auth::AuthController, introspection::IntrospectionController, jwks::JwksController, recovery::RecoveryController, register::RegisterController, sessions::SessionsController, tfa::TFAController, yggdrasil::YggdrasilController};
+use adjust::{controllers, database::{postgres::Postgres, redis::Redis, Pool}, controller::Controller};
+use tokio::net::TcpListener;
+use service::{cipher::CipherService, keystore::KeyStore, logic::tfa::TFAService, password::PasswordService, session::SessionService, totp::TOTPService, uuid::UuidService, yggdrasil_key::YggdrasilKey};
 
 mod repository;
 mod controller;
+mod extractor;
 mod service;
 mod models;
 mod schema;
@@ -16,11 +20,48 @@ pub struct AppState {
   redis: Pool<Redis>
 }
 
-#[main]
-async fn main() -> Service<'_, AppState> {
-  Service {
-    name: "Auth",
-    controllers: controllers![AuthController, SessionsController, RecoveryController, RegisterController, TFAController],
-    ..Default::default()
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+  // загружаем ключи заранее, чтобы не упасть на первом запросе
+  KeyStore::get();
+  YggdrasilKey::get();
+  CipherService::init()
+    .expect("Unable to load TOTP encryption keys!");
+  PasswordService::init()
+    .expect("Invalid Argon2 parameters!");
+  SessionService::init()
+    .expect("Unable to load TOKEN_HASH_KEY!");
+  TOTPService::init()
+    .expect("Invalid TOTP settings!");
+  UuidService::init()
+    .expect("Invalid MINECRAFT_UUID_MODE!");
+
+  let state = AppState::default();
+
+  // одноразовая миграция: шифруем открытые TOTP секреты
+  // и перешифровываем секреты, зашифрованные старыми ключами
+  if std::env::var("TOTP_REENCRYPT_SECRETS").is_ok_and(|v| v == "true") {
+    let mut db = state.postgres.get()
+      .expect("Unable to connect to the database!");
+
+    match TFAService::reencrypt_secrets(&mut db) {
+      Ok(count) => log::info!("re-encrypted {count} TOTP secrets"),
+      Err(e) => log::error!("unable to re-encrypt TOTP secrets: {e}")
+    }
   }
-}
\ No newline at end of file
+
+  log::info!("starting service Auth");
+
+  let router = controllers![AuthController, IntrospectionController, JwksController, SessionsController, RecoveryController, RegisterController, TFAController, YggdrasilController]
+    .into_iter()
+    .fold(Router::new(), |router, controller| controller.register(router))
+    .with_state(state);
+
+  let listener = TcpListener::bind("0.0.0.0:80")
+    .await?;
+
+  // поднимаем сервер сами (а не через adjust::Service), чтобы хэндлеры получали
+  // адрес соединения через ConnectInfo (нужен для IP клиента, см. misc::ClientIp)
+  Ok(axum::serve(listener, router.into_make_service_with_connect_info::<SocketAddr>())
+    .await?)
+}
@@ -1,4 +1,7 @@
-use axum::http::HeaderMap;
+use std::{env, net::IpAddr, str::FromStr};
+use adjust::response::{HttpError, NonJsonHttpResult};
+use data_encoding::BASE64;
+use axum::http::{header::{AUTHORIZATION, COOKIE}, HeaderMap, StatusCode};
 
 pub trait UserAgent {
   fn get_user_agent(&self) -> String;
@@ -11,4 +14,179 @@ impl UserAgent for HeaderMap {
       .and_then(|v| v.to_str().ok())
       .unwrap_or("n/a").to_owned()
   }
-}
\ No newline at end of file
+}
+
+pub trait BearerToken {
+  fn get_bearer_token(&self) -> NonJsonHttpResult<String>;
+}
+
+impl BearerToken for HeaderMap {
+  // достаёт токен из заголовка ``Authorization: Bearer <token>``
+  fn get_bearer_token(&self) -> NonJsonHttpResult<String> {
+    self
+      .get(AUTHORIZATION)
+      .and_then(|v| v.to_str().ok())
+      .and_then(|v| v.strip_prefix("Bearer "))
+      .map(|v| v.trim().to_owned())
+      .filter(|v| !v.is_empty())
+      .ok_or(HttpError::new("Требуется авторизация", Some(StatusCode::UNAUTHORIZED)))
+  }
+}
+
+pub trait ClientIp {
+  fn get_client_ip(&self, peer: IpAddr) -> IpAddr;
+}
+
+lazy_static::lazy_static! {
+  // адреса прокси перед сервисом, от которых принимаем ``X-Forwarded-For``
+  static ref TRUSTED_PROXIES: Vec<IpAddr> = env::var("TRUSTED_PROXIES")
+    .unwrap_or_default()
+    .split(',')
+    .filter_map(|ip| ip.trim().parse().ok())
+    .collect();
+}
+
+impl ClientIp for HeaderMap {
+  // IP клиента, peer - адрес, с которого пришло соединение
+  //
+  // ``X-Forwarded-For`` читаем, только если соединение пришло от доверенного прокси:
+  // каждый прокси дописывает адрес справа, поэтому идём справа налево и пропускаем
+  // доверенные прокси, первый чужой адрес - клиент (всё левее него клиент мог подделать)
+  fn get_client_ip(&self, peer: IpAddr) -> IpAddr {
+    forwarded_client_ip(self, peer, &TRUSTED_PROXIES)
+  }
+}
+
+fn forwarded_client_ip(
+  headers: &HeaderMap,
+  peer: IpAddr,
+  trusted: &[IpAddr]
+) -> IpAddr {
+  let mut client = peer;
+
+  if !trusted.contains(&client) {
+    return client;
+  }
+
+  let forwarded = headers
+    .get_all("x-forwarded-for")
+    .iter()
+    .filter_map(|v| v.to_str().ok())
+    .flat_map(|v| v.split(','))
+    .map(|v| v.trim().parse::<IpAddr>().ok())
+    .collect::<Vec<Option<IpAddr>>>();
+
+  for ip in forwarded.into_iter().rev() {
+    // мусор в заголовке - дальше не верим, клиентом считаем последний известный адрес
+    let Some(ip) = ip else {
+      break;
+    };
+
+    client = ip;
+
+    if !trusted.contains(&ip) {
+      break;
+    }
+  }
+
+  client
+}
+
+pub trait Cookies {
+  fn get_cookie(&self, name: &str) -> Option<String>;
+}
+
+impl Cookies for HeaderMap {
+  // достаёт значение куки из заголовков ``Cookie: a=1; b=2``
+  fn get_cookie(&self, name: &str) -> Option<String> {
+    self
+      .get_all(COOKIE)
+      .iter()
+      .filter_map(|v| v.to_str().ok())
+      .flat_map(|v| v.split(';'))
+      .filter_map(|pair| pair.trim().split_once('='))
+      .find(|(key, _)| *key == name)
+      .map(|(_, value)| value.trim().to_owned())
+      .filter(|v| !v.is_empty())
+  }
+}
+
+pub trait BasicCredentials {
+  fn get_basic_credentials(&self) -> NonJsonHttpResult<(String, String)>;
+}
+
+impl BasicCredentials for HeaderMap {
+  // достаёт логин и пароль из заголовка ``Authorization: Basic base64(login:password)``
+  fn get_basic_credentials(&self) -> NonJsonHttpResult<(String, String)> {
+    self
+      .get(AUTHORIZATION)
+      .and_then(|v| v.to_str().ok())
+      .and_then(|v| v.strip_prefix("Basic "))
+      .and_then(|v| BASE64.decode(v.trim().as_bytes()).ok())
+      .and_then(|v| String::from_utf8(v).ok())
+      .and_then(|v| v.split_once(':').map(|(login, password)| (login.to_owned(), password.to_owned())))
+      .ok_or(HttpError::new("Требуется авторизация", Some(StatusCode::UNAUTHORIZED)))
+  }
+}
+
+// читает переменную окружения, а если её нет (или она невалидная) - возвращает значение по умолчанию
+pub fn env_or<T: FromStr>(
+  name: &str,
+  default: T
+) -> T {
+  env::var(name)
+    .ok()
+    .and_then(|v| v.parse().ok())
+    .unwrap_or(default)
+}
+#[cfg(test)]
+mod tests {
+  use axum::http::HeaderValue;
+  use super::*;
+
+  fn ip(
+    value: &str
+  ) -> IpAddr {
+    value.parse().unwrap()
+  }
+
+  fn forwarded_for(
+    value: &str
+  ) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("x-forwarded-for", HeaderValue::from_str(value).unwrap());
+
+    headers
+  }
+
+  #[test]
+  fn uses_peer_without_trusted_proxies() {
+    let headers = forwarded_for("1.1.1.1");
+
+    assert_eq!(forwarded_client_ip(&headers, ip("2.2.2.2"), &[]), ip("2.2.2.2"));
+  }
+
+  #[test]
+  fn ignores_forwarded_for_from_untrusted_peer() {
+    let headers = forwarded_for("1.1.1.1");
+
+    assert_eq!(forwarded_client_ip(&headers, ip("2.2.2.2"), &[ip("10.0.0.1")]), ip("2.2.2.2"));
+  }
+
+  #[test]
+  fn takes_rightmost_untrusted_address_from_trusted_peer() {
+    let trusted = [ip("10.0.0.1"), ip("10.0.0.2")];
+    let headers = forwarded_for("6.6.6.6, 1.1.1.1, 10.0.0.2");
+
+    assert_eq!(forwarded_client_ip(&headers, ip("10.0.0.1"), &trusted), ip("1.1.1.1"));
+  }
+
+  #[test]
+  fn stops_at_garbage_in_forwarded_for() {
+    let trusted = [ip("10.0.0.1"), ip("10.0.0.2")];
+    let headers = forwarded_for("1.1.1.1, garbage, 10.0.0.2");
+
+    assert_eq!(forwarded_client_ip(&headers, ip("10.0.0.1"), &trusted), ip("10.0.0.2"));
+    assert_eq!(forwarded_client_ip(&HeaderMap::new(), ip("10.0.0.1"), &trusted), ip("10.0.0.1"));
+  }
+}
@@ -3,7 +3,7 @@
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
-use crate::schema::{sessions, users};
+use crate::{schema::{sessions, users}, service::uuid::UuidService};
 
 #[derive(Queryable, Selectable, Serialize, Deserialize, Clone)]
 #[diesel(table_name = users)]
@@ -23,6 +23,12 @@ pub struct User {
   pub totp_secret: Option<String>,
   #[diesel(sql_type = Nullable<Jsonb>)]
   pub backup_codes: Option<serde_json::Value>,
+  /// Айди ключа, которым зашифрован ``totp_secret``
+  #[diesel(sql_type = Nullable<Text>)]
+  pub totp_key_id: Option<String>,
+  /// Minecraft UUID (без дефисов)
+  #[diesel(sql_type = Text)]
+  pub uuid: String,
 }
 
 #[derive(Deserialize)]
@@ -48,7 +54,9 @@ pub struct UserAdd {
   pub user_id: Option<i32>,
   pub username: String,
   pub password: String,
-  pub salt: String
+  pub salt: String,
+  /// Minecraft UUID (без дефисов)
+  pub uuid: String
 }
 
 #[derive(Serialize, Deserialize)]
@@ -60,7 +68,9 @@ pub struct UserCreate {
 #[derive(Serialize, Deserialize)]
 pub struct BaseUserInfo {
   pub id: i32,
-  pub username: String
+  pub username: String,
+  /// Minecraft UUID (без дефисов)
+  pub uuid: String
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -84,19 +94,16 @@ impl From<UserRegister> for UserCreate {
 
 impl From<UserRegister> for UserAdd {
   fn from(value: UserRegister) -> Self {
+    let uuid = UuidService::generate(&value.username);
+
     UserAdd {
       user_id: None,
       username: value.username,
       password: value.password,
-      // использовать unwrap в этом случае - не безответственно
-      // поток из-за него не свалится))
-      //
-      // потому-что нет сценариев, при которых
-      // salt равнялся бы None
-      //
-      // в любом случае, использовать unwrap
-      // не круто
-      salt: value.salt.unwrap()
+      // у паролей в формате Argon2id соль хранится
+      // внутри самого хэша, так что колонка salt пустая
+      salt: value.salt.unwrap_or_default(),
+      uuid
     }
   }
 }
@@ -104,7 +111,13 @@ impl From<UserRegister> for UserAdd {
 #[derive(Serialize, Deserialize)]
 pub struct UserLogin {
   pub username: String,
-  pub password: String
+  pub password: String,
+  /// "Запомнить меня" - выдаёт refresh токен с более долгим сроком жизни
+  #[serde(default)]
+  pub remember_me: bool,
+  /// Айди установки клиента (постоянный для одной установки)
+  #[serde(default)]
+  pub device_id: Option<String>
 }
 
 #[derive(Serialize, Deserialize)]
@@ -128,14 +141,46 @@ pub struct Session {
   pub global_id: i32,
   #[diesel(sql_type = Text)]
   pub useragent: String,
-  #[diesel(sql_type = Text)]
-  pub jwt: String,
-  #[diesel(sql_type = Text)]
-  pub refresh_token: String,
   #[diesel(sql_type = Boolean)]
   pub is_active: bool,
   #[diesel(sql_type = Timestamp)]
   pub last_activity: NaiveDateTime,
+  /// Айди семейства refresh токенов (одно на сессию)
+  #[diesel(sql_type = Text)]
+  pub family_id: String,
+  /// Номер текущего refresh токена в семействе
+  #[diesel(sql_type = Integer)]
+  pub refresh_generation: i32,
+  /// Сессия завершена из-за повторного использования refresh токена
+  #[diesel(sql_type = Boolean)]
+  pub compromised: bool,
+  /// HMAC от текущего refresh токена (сами токены в бд не храним)
+  #[serde(skip_serializing)]
+  #[diesel(sql_type = Text)]
+  pub refresh_token_hash: String,
+  /// Сессия создана с "запомнить меня"
+  #[diesel(sql_type = Boolean)]
+  pub remember: bool,
+  #[diesel(sql_type = Timestamp)]
+  pub created_at: NaiveDateTime,
+  /// Айди установки клиента
+  #[diesel(sql_type = Nullable<Text>)]
+  pub device_id: Option<String>,
+  /// clientToken лаунчера (только у сессий Yggdrasil)
+  #[serde(skip_serializing)]
+  #[diesel(sql_type = Nullable<Text>)]
+  pub client_token: Option<String>,
+}
+
+/// Сессия вместе с выданными ей токенами
+#[derive(Serialize, Clone)]
+pub struct SessionWithTokens {
+  #[serde(flatten)]
+  pub session: Session,
+  pub jwt: String,
+  pub refresh_token: String,
+  /// Minecraft UUID владельца сессии (без дефисов)
+  pub uuid: String,
 }
 
 #[derive(Queryable, Selectable, Serialize, Deserialize, Clone)]
@@ -145,7 +190,8 @@ pub struct SessionSafe {
   pub id: i32,
   pub global_id: i32,
   pub useragent: String,
-  pub last_activity: NaiveDateTime
+  pub last_activity: NaiveDateTime,
+  pub device_id: Option<String>
 }
 
 #[derive(Queryable, Selectable, Insertable, Serialize, Deserialize, Clone)]
@@ -158,18 +204,30 @@ pub struct SessionCreate {
   pub global_id: i32,
   #[diesel(sql_type = Text)]
   pub useragent: String,
+  #[diesel(sql_type = Timestamp)]
+  pub last_activity: NaiveDateTime,
   #[diesel(sql_type = Text)]
-  pub jwt: String,
+  pub family_id: String,
   #[diesel(sql_type = Text)]
-  pub refresh_token: String,
+  pub refresh_token_hash: String,
+  #[diesel(sql_type = Boolean)]
+  pub remember: bool,
   #[diesel(sql_type = Timestamp)]
-  pub last_activity: NaiveDateTime,
+  pub created_at: NaiveDateTime,
+  #[diesel(sql_type = Nullable<Text>)]
+  pub device_id: Option<String>,
+  #[diesel(sql_type = Nullable<Text>)]
+  pub client_token: Option<String>,
 }
 
 #[derive(Queryable, Selectable, Insertable, AsChangeset, Serialize, Deserialize, Clone)]
 #[diesel(table_name = sessions)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
-pub struct SessionUpdateJwt {
+pub struct SessionRotate {
   #[diesel(sql_type = Text)]
-  pub jwt: String,
+  pub refresh_token_hash: String,
+  #[diesel(sql_type = Integer)]
+  pub refresh_generation: i32,
+  #[diesel(sql_type = Timestamp)]
+  pub last_activity: NaiveDateTime,
 }
\ No newline at end of file
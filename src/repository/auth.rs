@@ -1,10 +1,10 @@
 #![allow(dead_code)]
 
 use anyhow::{anyhow, Result};
-use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl};
 use adjust::{database::{postgres::Postgres, Database}, response::{HttpError, NonJsonHttpResult}};
 use reqwest::StatusCode;
-use crate::{models::{User, UserAdd, UserPasswordUpdate}, schema::users};
+use crate::{models::{User, UserAdd, UserPasswordUpdate}, schema::users, service::cipher::Encrypted};
 
 use super::user::UserRepository;
 
@@ -42,6 +42,26 @@ impl AuthRepository {
       .map_err(|_| anyhow!("Пользователь не был найден"))
   }
 
+  pub fn find_by_usernames(
+    db: &mut Database<Postgres>,
+    usernames: &[String]
+  ) -> Result<Vec<User>> {
+    Ok(users::table
+      .filter(users::columns::username.eq_any(usernames))
+      .load::<User>(db)?)
+  }
+
+  // uuid - Minecraft UUID без дефисов
+  pub fn find_by_uuid(
+    db: &mut Database<Postgres>,
+    uuid: &str
+  ) -> Result<User> {
+    users::table
+      .filter(users::columns::uuid.eq(uuid))
+      .first::<User>(db)
+      .map_err(|_| anyhow!("Пользователь не был найден"))
+  }
+
   pub async fn check_userdata_taken(
     db: &mut Database<Postgres>,
     username: &str,
@@ -81,13 +101,72 @@ impl AuthRepository {
     Ok(())
   }
 
+  pub fn update_backup_codes(
+    db: &mut Database<Postgres>,
+    id: i32,
+    codes: serde_json::Value
+  ) -> Result<()> {
+    diesel::update(users::table.filter(users::columns::id.eq(id)))
+      .set(users::columns::backup_codes.eq(codes))
+      .execute(db)?;
+
+    Ok(())
+  }
+
+  // заменяет резервные коды, только если они не поменялись с момента чтения
+  // возвращает false, если кто-то успел изменить их раньше нас
+  pub fn replace_backup_codes(
+    db: &mut Database<Postgres>,
+    id: i32,
+    old: serde_json::Value,
+    new: serde_json::Value
+  ) -> Result<bool> {
+    let updated = diesel::update(users::table
+        .filter(users::columns::id.eq(id))
+        .filter(users::columns::backup_codes.eq(old)))
+      .set(users::columns::backup_codes.eq(new))
+      .execute(db)?;
+
+    Ok(updated == 1)
+  }
+
   pub fn update_totp(
     db: &mut Database<Postgres>,
     id: i32,
-    secret: String
+    secret: Encrypted
+  ) -> Result<()> {
+    diesel::update(users::table.filter(users::columns::id.eq(id)))
+      .set((
+        users::columns::totp_secret.eq(secret.ciphertext),
+        users::columns::totp_key_id.eq(secret.key_id)
+      ))
+      .execute(db)?;
+
+    Ok(())
+  }
+
+  // пользователи, у которых TOTP секрет не зашифрован или зашифрован не активным ключом
+  pub fn find_with_stale_totp(
+    db: &mut Database<Postgres>,
+    active_key_id: &str
+  ) -> Result<Vec<User>> {
+    Ok(users::table
+      .filter(users::columns::totp_secret.is_not_null())
+      .filter(users::columns::totp_key_id.is_null().or(users::columns::totp_key_id.ne(active_key_id)))
+      .load::<User>(db)?)
+  }
+
+  // отвязывает 2FA вместе с резервными кодами
+  pub fn remove_totp(
+    db: &mut Database<Postgres>,
+    id: i32
   ) -> Result<()> {
     diesel::update(users::table.filter(users::columns::id.eq(id)))
-      .set(users::columns::totp_secret.eq(secret))
+      .set((
+        users::columns::totp_secret.eq(None::<String>),
+        users::columns::totp_key_id.eq(None::<String>),
+        users::columns::backup_codes.eq(None::<serde_json::Value>)
+      ))
       .execute(db)?;
 
     Ok(())
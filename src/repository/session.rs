@@ -1,35 +1,14 @@
 #![allow(dead_code)]
 
 use anyhow::Result;
-use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl};
+use chrono::NaiveDateTime;
+use diesel::{BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
 use adjust::{database::{postgres::Postgres, Database}, response::{HttpError, NonJsonHttpResult}};
-use crate::{models::{Session, SessionCreate, SessionSafe, SessionUpdateJwt}, schema::sessions};
+use crate::{models::{Session, SessionCreate, SessionRotate, SessionSafe}, schema::sessions};
 
 pub struct SessionRepository;
 
 impl SessionRepository {
-  pub fn update_jwt(
-    db: &mut Database<Postgres>,
-    session_id: i32,
-    jwt: String
-  ) -> Result<()> {
-    diesel::update(sessions::table.filter(sessions::columns::id.eq(session_id)))
-      .set(SessionUpdateJwt {jwt})
-      .execute(db)?;
-
-    Ok(())
-  }
-
-  pub fn update(
-    db: &mut Database<Postgres>,
-    session_id: i32,
-    jwt: String
-  ) -> Result<Session> {
-    Ok(diesel::update(sessions::table.filter(sessions::columns::id.eq(session_id)))
-      .set(SessionUpdateJwt {jwt})
-      .get_result::<Session>(db)?)
-  }
-
   pub fn add(
     db: &mut Database<Postgres>,
     session: SessionCreate
@@ -39,22 +18,58 @@ impl SessionRepository {
       .get_result::<Session>(db)?)
   }
 
-  pub fn find_by_refresh(
+  pub fn find(
     db: &mut Database<Postgres>,
-    refresh: String
+    id: i32
   ) -> Result<Session> {
     Ok(sessions::table
-      .filter(sessions::columns::refresh_token.eq(refresh))
+      .filter(sessions::columns::id.eq(id))
       .first::<Session>(db)?)
   }
 
-  pub fn find_by_jwt(
+  // выдаёт сессии новую пару токенов,
+  // только если refresh токен не был обновлён с момента чтения
+  pub fn rotate(
     db: &mut Database<Postgres>,
-    jwt: String
-  ) -> Result<Session> {
-    Ok(sessions::table
-      .filter(sessions::columns::jwt.eq(jwt))
-      .first::<Session>(db)?)
+    session_id: i32,
+    generation: i32,
+    data: SessionRotate
+  ) -> Result<Option<Session>> {
+    Ok(diesel::update(sessions::table
+        .filter(sessions::columns::id.eq(session_id))
+        .filter(sessions::columns::refresh_generation.eq(generation))
+        .filter(sessions::columns::is_active.eq(true)))
+      .set(data)
+      .get_result::<Session>(db)
+      .optional()?)
+  }
+
+  // обновляет время последней активности в сессии
+  pub fn touch(
+    db: &mut Database<Postgres>,
+    id: i32,
+    time: NaiveDateTime
+  ) -> Result<()> {
+    diesel::update(sessions::table.filter(sessions::id.eq(id)))
+      .set(sessions::last_activity.eq(time))
+      .execute(db)?;
+
+    Ok(())
+  }
+
+  // завершает сессию и помечает её скомпрометированной
+  pub fn mark_compromised(
+    db: &mut Database<Postgres>,
+    id: i32
+  ) -> Result<()> {
+    diesel::update(sessions::table.filter(sessions::id.eq(id)))
+      .set((
+        sessions::is_active.eq(false),
+        sessions::compromised.eq(true)
+      ))
+      .execute(db)?;
+
+    Ok(())
   }
 
   pub fn delete(
@@ -68,6 +83,36 @@ impl SessionRepository {
     Ok(())
   }
 
+  // деактивирует все сессии пользователя, кроме указанной
+  // и возвращает айди деактивированных сессий
+  pub fn delete_others(
+    db: &mut Database<Postgres>,
+    user_id: i32,
+    except_id: i32
+  ) -> Result<Vec<i32>> {
+    Ok(diesel::update(sessions::table
+        .filter(sessions::user_id.eq(user_id))
+        .filter(sessions::id.ne(except_id))
+        .filter(sessions::is_active.eq(true)))
+      .set(sessions::is_active.eq(false))
+      .returning(sessions::id)
+      .get_results::<i32>(db)?)
+  }
+
+  // деактивирует все сессии пользователя
+  // и возвращает айди деактивированных сессий
+  pub fn delete_all(
+    db: &mut Database<Postgres>,
+    user_id: i32
+  ) -> Result<Vec<i32>> {
+    Ok(diesel::update(sessions::table
+        .filter(sessions::user_id.eq(user_id))
+        .filter(sessions::is_active.eq(true)))
+      .set(sessions::is_active.eq(false))
+      .returning(sessions::id)
+      .get_results::<i32>(db)?)
+  }
+
   pub fn get_sessions(
     db: &mut Database<Postgres>,
     user_id: i32
@@ -79,22 +124,71 @@ impl SessionRepository {
         sessions::global_id,
         sessions::useragent,
         sessions::last_activity,
+        sessions::device_id,
       ))
       .get_results::<SessionSafe>(db)
       .map_err(|_| HttpError::new("Сессии не были найдены", None))
   }
 
-  pub fn get(
+  // деактивирует сессии пользователя на устройстве
+  // и возвращает айди деактивированных сессий
+  pub fn delete_by_device(
+    db: &mut Database<Postgres>,
+    user_id: i32,
+    device_id: &str
+  ) -> Result<Vec<i32>> {
+    Ok(diesel::update(sessions::table
+        .filter(sessions::user_id.eq(user_id))
+        .filter(sessions::device_id.eq(device_id))
+        .filter(sessions::is_active.eq(true)))
+      .set(sessions::is_active.eq(false))
+      .returning(sessions::id)
+      .get_results::<i32>(db)?)
+  }
+
+  // деактивирует сессии пользователя в лаунчере с этим clientToken
+  // и возвращает айди деактивированных сессий
+  pub fn delete_by_client_token(
+    db: &mut Database<Postgres>,
+    user_id: i32,
+    client_token: &str
+  ) -> Result<Vec<i32>> {
+    Ok(diesel::update(sessions::table
+        .filter(sessions::user_id.eq(user_id))
+        .filter(sessions::client_token.eq(client_token))
+        .filter(sessions::is_active.eq(true)))
+      .set(sessions::is_active.eq(false))
+      .returning(sessions::id)
+      .get_results::<i32>(db)?)
+  }
+
+  // деактивирует все сессии пользователя из лаунчеров (Yggdrasil)
+  // и возвращает айди деактивированных сессий
+  pub fn delete_launcher_sessions(
+    db: &mut Database<Postgres>,
+    user_id: i32
+  ) -> Result<Vec<i32>> {
+    Ok(diesel::update(sessions::table
+        .filter(sessions::user_id.eq(user_id))
+        .filter(sessions::client_token.is_not_null())
+        .filter(sessions::is_active.eq(true)))
+      .set(sessions::is_active.eq(false))
+      .returning(sessions::id)
+      .get_results::<i32>(db)?)
+  }
+
+  // айди активных сессий пользователя, кроме keep самых новых
+  pub fn find_oldest(
     db: &mut Database<Postgres>,
     user_id: i32,
-    user_agent: &str,
-  ) -> diesel::result::QueryResult<Session> {
-    let session = sessions::table
+    keep: i64
+  ) -> Result<Vec<i32>> {
+    Ok(sessions::table
       .filter(sessions::user_id.eq(user_id))
-      .filter(sessions::useragent.eq(user_agent))
       .filter(sessions::is_active.eq(true))
-      .first::<Session>(db)?;
-
-    Ok(session)
+      .order(sessions::created_at.desc())
+      .offset(keep)
+      .select(sessions::id)
+      .get_results::<i32>(db)?)
   }
 }
\ No newline at end of file
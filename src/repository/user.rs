@@ -45,6 +45,21 @@ impl UserRepository {
     Ok(json)
   }
 
+  pub async fn find(
+    id: i32
+  ) -> Result<UserInUserService> {
+    let res = CLIENT.get(format!("http://{}/user/{}", *USER_URL, id))
+      .send()
+      .await
+      .map_err(|e| anyhow::anyhow!("Не получилось отправить запрос на сервис user: {e}"))?
+      .error_for_status()?;
+
+    let json = res.json::<UserInUserService>()
+      .await?;
+
+    Ok(json)
+  }
+
   pub async fn find_by_email(
     email: &str
   ) -> Result<UserInUserService> {
@@ -6,10 +6,16 @@ diesel::table! {
         user_id -> Int4,
         global_id -> Int4,
         useragent -> Text,
-        jwt -> Text,
-        refresh_token -> Text,
         is_active -> Bool,
         last_activity -> Timestamp,
+        family_id -> Text,
+        refresh_generation -> Int4,
+        compromised -> Bool,
+        refresh_token_hash -> Text,
+        remember -> Bool,
+        created_at -> Timestamp,
+        device_id -> Nullable<Text>,
+        client_token -> Nullable<Text>,
     }
 }
 
@@ -22,6 +28,8 @@ diesel::table! {
         salt -> Text,
         totp_secret -> Nullable<Text>,
         backup_codes -> Nullable<Jsonb>,
+        totp_key_id -> Nullable<Text>,
+        uuid -> Text,
     }
 }
 
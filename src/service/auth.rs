@@ -1,68 +1,212 @@
 #![allow(dead_code)]
 
 use axum::Json;
-use crate::{models::{BaseUserInfo, Session, UserLogin}, repository::{auth::AuthRepository, session::SessionRepository}, service::jwt::JWTService};
-use super::{hasher::HasherService, logic::tfa::TFAService, session::SessionService};
-use adjust::{database::{postgres::Postgres, redis::Redis, Database}, response::{HttpError, HttpResult}};
-use reqwest::StatusCode;
+use crate::{models::{BaseUserInfo, SessionWithTokens, User, UserLogin, UserPasswordUpdate}, repository::auth::AuthRepository};
+use super::{jwt::Claims, logic::tfa::TFAService, owner_cache::OwnerCacheService, password::PasswordService, session::SessionService};
+use adjust::{database::{postgres::Postgres, redis::Redis, Database}, response::{HttpError, HttpMessage, HttpResult, NonJsonHttpResult}};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+// что экранируется в текстовых заголовках /verify
+// (ники из латиницы, цифр и _ остаются как есть)
+const HEADER_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+  .remove(b'_')
+  .remove(b'-')
+  .remove(b'.');
 
 pub struct AuthService;
 
 impl AuthService {
   pub fn get_owner(
     db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
     token: String
   ) -> HttpResult<BaseUserInfo> {
-    let session = SessionService::get_by_jwt(db, token, true)?;
+    let (_, info) = Self::resolve_owner(db, redis, &token)?;
+
+    Ok(Json(info))
+  }
+
+  // проверяет токен и возвращает его claims и владельца
+  fn resolve_owner(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    token: &str
+  ) -> NonJsonHttpResult<(Claims, BaseUserInfo)> {
+    // подпись и список отзыва проверяем всегда, в бд идём только если записи нет в кэше
+    let Json(claims) = SessionService::verify_access(redis, token)
+      .map_err(|_| HttpError::new("Сессия не была найдена", Some(StatusCode::BAD_REQUEST)))?;
+
+    if let Some(info) = OwnerCacheService::get(redis, &claims)? {
+      return Ok((claims, info));
+    }
+
+    let session = SessionService::get_by_claims(db, redis, &claims, true)?;
+    SessionService::touch(db, session.id)?;
+
     let user_id = session.user_id;
     let user = AuthRepository::find(db, user_id)?;
 
-    Ok(Json(BaseUserInfo {
+    let info = BaseUserInfo {
       id: user_id,
-      username: user.username
-    }))
+      username: user.username,
+      uuid: user.uuid
+    };
+
+    // запись не должна пережить сессию (ни по простою, ни по максимальному возрасту)
+    OwnerCacheService::put(redis, &claims, &info, SessionService::lifetime_after_touch(&session))?;
+
+    Ok((claims, info))
   }
 
-  // авторизация
-  pub async fn login(
+  // forward-auth для nginx auth_request / Traefik ForwardAuth:
+  // при успехе отдаёт данные пользователя в заголовках ответа
+  pub fn verify(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    token: &str
+  ) -> NonJsonHttpResult<HeaderMap> {
+    let unauthorized = || HttpError::new("Невалидный или истёкший токен", Some(StatusCode::UNAUTHORIZED));
+
+    let (claims, info) = Self::resolve_owner(db, redis, token)
+      .map_err(|_| unauthorized())?;
+
+    // сессия валидна, так что ошибка здесь - наша, а не клиента
+    Self::verify_headers(&info, claims.rank.as_deref().unwrap_or_default(), claims.sid)
+      .map_err(|e| HttpError(e, Some(StatusCode::INTERNAL_SERVER_ERROR)))
+  }
+
+  // заголовки ответа /verify
+  // ник и ранг могут быть не ASCII, поэтому передаются в percent-encoding (UTF-8)
+  fn verify_headers(
+    info: &BaseUserInfo,
+    rank: &str,
+    session_id: i32
+  ) -> anyhow::Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+
+    let values = [
+      ("x-user-id", info.id.to_string()),
+      ("x-username", utf8_percent_encode(&info.username, HEADER_ENCODE_SET).to_string()),
+      ("x-minecraft-uuid", info.uuid.clone()),
+      ("x-user-rank", utf8_percent_encode(rank, HEADER_ENCODE_SET).to_string()),
+      ("x-session-id", session_id.to_string())
+    ];
+
+    for (name, value) in values {
+      headers.insert(name, HeaderValue::from_str(&value)?);
+    }
+
+    Ok(headers)
+  }
+
+  // сбрасывает кэш /owner пользователя (например, после смены ника в сервисе user)
+  pub fn invalidate_owner(
     redis: &mut Database<Redis>,
+    user_id: i32
+  ) -> HttpResult<HttpMessage> {
+    OwnerCacheService::invalidate(redis, user_id)?;
+
+    Ok(Json(HttpMessage::new("Кэш пользователя был сброшен")))
+  }
+
+  // проверяет ник и пароль и возвращает пользователя
+  pub fn check_credentials(
     db: &mut Database<Postgres>,
-    credentials: UserLogin,
-    user_agent: &str
-  ) -> HttpResult<serde_json::Value> {
+    username: &String,
+    password: &str
+  ) -> NonJsonHttpResult<User> {
     // ищем юзера по нику
-    let user = AuthRepository::find_by_username(db, &credentials.username)?;
-    let password = HasherService::sha256(credentials.password + &user.salt);
+    let user = AuthRepository::find_by_username(db, username)?;
 
     // проверяем пароль на валидность
-    if user.password != password {
+    if !PasswordService::verify(password, &user.password, &user.salt) {
       return Err(HttpError::new("Неверный пароль!", Some(StatusCode::UNAUTHORIZED)));
     }
 
+    // если пароль захэширован устаревшим алгоритмом (или с другими параметрами)
+    // то тихо перехэшируем его, пока у нас есть открытый пароль
+    if PasswordService::needs_rehash(&user.password) {
+      let password = PasswordService::hash(password)?;
+
+      AuthRepository::update(db, user.id, UserPasswordUpdate { salt: String::new(), password })?;
+    }
+
+    Ok(user)
+  }
+
+  // авторизация
+  pub async fn login(
+    redis: &mut Database<Redis>,
+    db: &mut Database<Postgres>,
+    credentials: UserLogin,
+    user_agent: &str
+  ) -> HttpResult<serde_json::Value> {
+    let user = Self::check_credentials(db, &credentials.username, &credentials.password)?;
+
     // если у игрока привязан 2fa
-    // то добавляем в редис запись на 3 минуты
+    // то выдаём одноразовый тикет на 5 минут
     // и ждем пока игрок авторизируется
     if user.totp_secret.is_some() {
-      let data = TFAService::add_login_attempt(redis, user.username)?;
+      let data = TFAService::add_login_attempt(redis, user.id, user_agent, credentials.remember_me, credentials.device_id)?;
 
       return Ok(Json(serde_json::to_value(data.0)?));
     }
 
     // создаем сессию в любом случае
-    let session = SessionService::get(db, user, user_agent)?;
+    let session = SessionService::create(db, redis, user, user_agent, credentials.remember_me, credentials.device_id)
+      .await?;
 
     Ok(Json(serde_json::to_value((*session).clone())?))
   }
 
-  // обновляет и возвращает jwt
+  // обновляет и возвращает jwt и refresh токен
   // с помощью refresh токена
   pub async fn refresh(
     db: &mut Database<Postgres>,
-    refresh_token: String
-  ) -> HttpResult<Session> {
-    let session = SessionService::get_by_refresh(db, refresh_token, true)?;
-    let token = JWTService::generate(session.user_id)?;
+    redis: &mut Database<Redis>,
+    refresh_token: String,
+    access_token: Option<String>
+  ) -> HttpResult<SessionWithTokens> {
+    SessionService::rotate(db, redis, refresh_token, access_token)
+      .await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn info(
+    username: &str
+  ) -> BaseUserInfo {
+    BaseUserInfo {
+      id: 7,
+      username: username.to_owned(),
+      uuid: String::from("b50ad385829d3141a2167e7d7539ba7f")
+    }
+  }
+
+  #[test]
+  fn keeps_ascii_usernames_as_is() {
+    let headers = AuthService::verify_headers(&info("Notch_1"), "admin", 3).unwrap();
+
+    assert_eq!(headers["x-user-id"], "7");
+    assert_eq!(headers["x-username"], "Notch_1");
+    assert_eq!(headers["x-minecraft-uuid"], "b50ad385829d3141a2167e7d7539ba7f");
+    assert_eq!(headers["x-user-rank"], "admin");
+    assert_eq!(headers["x-session-id"], "3");
+  }
+
+  #[test]
+  fn percent_encodes_non_ascii_usernames() {
+    let headers = AuthService::verify_headers(&info("Пётр_1"), "игрок", 3).unwrap();
 
-    Ok(Json(SessionRepository::update(db, session.id, token)?))
+    assert_eq!(headers["x-username"], "%D0%9F%D1%91%D1%82%D1%80_1");
+    assert_eq!(
+      percent_encoding::percent_decode_str(headers["x-username"].to_str().unwrap()).decode_utf8().unwrap(),
+      "Пётр_1"
+    );
+    assert_eq!(headers["x-user-rank"], "%D0%B8%D0%B3%D1%80%D0%BE%D0%BA");
   }
-}
\ No newline at end of file
+}
@@ -0,0 +1,213 @@
+#![allow(dead_code)]
+
+use std::{env, sync::OnceLock};
+use aes_gcm::{aead::{Aead, AeadCore, OsRng, Payload}, Aes256Gcm, KeyInit, Nonce};
+use anyhow::{anyhow, bail, Context, Result};
+use data_encoding::BASE64;
+use hashbrown::HashMap;
+
+// длина nonce для AES-GCM
+const NONCE_LENGTH: usize = 12;
+
+// ключи шифрования, загружаются при запуске в CipherService::init
+static KEYS: OnceLock<CipherKeys> = OnceLock::new();
+
+/// Зашифрованное значение и айди ключа, которым оно зашифровано
+pub struct Encrypted {
+  pub key_id: String,
+  /// base64(nonce + ciphertext)
+  pub ciphertext: String
+}
+
+/// Набор ключей и айди ключа, которым шифруются новые секреты
+struct CipherKeys {
+  keys: HashMap<String, Aes256Gcm>,
+  active: String
+}
+
+impl CipherKeys {
+  // ключи в формате ``kid:base64,kid:base64``
+  fn parse(
+    keys: &str,
+    active: &str
+  ) -> Result<CipherKeys> {
+    let keys = keys.split(',')
+      .map(|pair| Self::parse_key(pair.trim()))
+      .collect::<Result<HashMap<String, Aes256Gcm>>>()?;
+
+    if !keys.contains_key(active) {
+      bail!("Active TOTP encryption key {active} not found");
+    }
+
+    Ok(CipherKeys { keys, active: active.to_owned() })
+  }
+
+  fn parse_key(
+    pair: &str
+  ) -> Result<(String, Aes256Gcm)> {
+    let (kid, key) = pair.split_once(':')
+      .ok_or(anyhow!("Key must be in kid:base64 format"))?;
+
+    let key = BASE64.decode(key.as_bytes())
+      .with_context(|| format!("Key {kid} is not valid base64"))?;
+
+    let cipher = Aes256Gcm::new_from_slice(&key)
+      .map_err(|_| anyhow!("Key {kid} must be 32 bytes long"))?;
+
+    Ok((kid.to_owned(), cipher))
+  }
+
+  fn encrypt(
+    &self,
+    aad: &[u8],
+    plaintext: &str
+  ) -> Result<Encrypted> {
+    let cipher = self.keys.get(&self.active)
+      .ok_or(anyhow!("Active encryption key not found"))?;
+
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, Payload { msg: plaintext.as_bytes(), aad })
+      .map_err(|_| anyhow!("Не получилось зашифровать данные"))?;
+
+    let mut data = nonce.to_vec();
+    data.extend(ciphertext);
+
+    Ok(Encrypted {
+      key_id: self.active.clone(),
+      ciphertext: BASE64.encode(&data)
+    })
+  }
+
+  fn decrypt(
+    &self,
+    aad: &[u8],
+    key_id: &str,
+    ciphertext: &str
+  ) -> Result<String> {
+    let cipher = self.keys.get(key_id)
+      .ok_or(anyhow!("Ключ шифрования {key_id} не найден"))?;
+
+    let data = BASE64.decode(ciphertext.as_bytes())?;
+
+    if data.len() < NONCE_LENGTH {
+      bail!("Повреждённый шифротекст");
+    }
+
+    let (nonce, ciphertext) = data.split_at(NONCE_LENGTH);
+
+    let plaintext = cipher.decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+      .map_err(|_| anyhow!("Не получилось расшифровать данные"))?;
+
+    Ok(String::from_utf8(plaintext)?)
+  }
+}
+
+/// AEAD шифрование (AES-256-GCM) для данных, которые хранятся в бд
+pub struct CipherService;
+
+impl CipherService {
+  // загружает ключи из ``TOTP_ENCRYPTION_KEYS`` и ``TOTP_ENCRYPTION_KEY_ID``
+  // и проверяет, что активный ключ существует
+  pub fn init() -> Result<()> {
+    let keys = env::var("TOTP_ENCRYPTION_KEYS")
+      .context("The TOTP_ENCRYPTION_KEYS environment variable was not found")?;
+    let active = env::var("TOTP_ENCRYPTION_KEY_ID")
+      .context("The TOTP_ENCRYPTION_KEY_ID environment variable was not found")?;
+
+    // повторный вызов оставляет уже загруженные ключи
+    let _ = KEYS.set(CipherKeys::parse(&keys, &active)?);
+
+    Ok(())
+  }
+
+  fn keys() -> &'static CipherKeys {
+    KEYS.get()
+      .expect("CipherService::init must be called at startup")
+  }
+
+  pub fn active_key_id() -> &'static str {
+    &Self::keys().active
+  }
+
+  // шифрует значение активным ключом
+  // aad привязывает шифротекст к записи (его нельзя переложить другому пользователю)
+  pub fn encrypt(
+    aad: &[u8],
+    plaintext: &str
+  ) -> Result<Encrypted> {
+    Self::keys().encrypt(aad, plaintext)
+  }
+
+  // расшифровывает значение ключом key_id
+  pub fn decrypt(
+    aad: &[u8],
+    key_id: &str,
+    ciphertext: &str
+  ) -> Result<String> {
+    Self::keys().decrypt(aad, key_id, ciphertext)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // 32 байта нулей и 32 байта единиц
+  const OLD_KEY: &str = "old:AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+  const NEW_KEY: &str = "new:AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=";
+
+  fn keys() -> CipherKeys {
+    CipherKeys::parse(&format!("{OLD_KEY}, {NEW_KEY}"), "new").unwrap()
+  }
+
+  #[test]
+  fn round_trips_with_active_key() {
+    let keys = keys();
+    let encrypted = keys.encrypt(b"user:1", "JBSWY3DPEHPK3PXP").unwrap();
+
+    assert_eq!(encrypted.key_id, "new");
+    assert_eq!(keys.decrypt(b"user:1", &encrypted.key_id, &encrypted.ciphertext).unwrap(), "JBSWY3DPEHPK3PXP");
+  }
+
+  #[test]
+  fn uses_fresh_nonce_each_time() {
+    let keys = keys();
+
+    let first = keys.encrypt(b"user:1", "secret").unwrap();
+    let second = keys.encrypt(b"user:1", "secret").unwrap();
+
+    assert_ne!(first.ciphertext, second.ciphertext);
+  }
+
+  #[test]
+  fn decrypts_with_rotated_out_key() {
+    let old = CipherKeys::parse(OLD_KEY, "old").unwrap();
+    let encrypted = old.encrypt(b"user:1", "secret").unwrap();
+
+    assert_eq!(keys().decrypt(b"user:1", "old", &encrypted.ciphertext).unwrap(), "secret");
+  }
+
+  #[test]
+  fn rejects_wrong_aad_key_and_tampering() {
+    let keys = keys();
+    let encrypted = keys.encrypt(b"user:1", "secret").unwrap();
+
+    assert!(keys.decrypt(b"user:2", "new", &encrypted.ciphertext).is_err());
+    assert!(keys.decrypt(b"user:1", "old", &encrypted.ciphertext).is_err());
+    assert!(keys.decrypt(b"user:1", "missing", &encrypted.ciphertext).is_err());
+
+    let mut data = BASE64.decode(encrypted.ciphertext.as_bytes()).unwrap();
+    *data.last_mut().unwrap() ^= 1;
+
+    assert!(keys.decrypt(b"user:1", "new", &BASE64.encode(&data)).is_err());
+    assert!(keys.decrypt(b"user:1", "new", "AAAA").is_err());
+  }
+
+  #[test]
+  fn rejects_malformed_config() {
+    assert!(CipherKeys::parse(OLD_KEY, "new").is_err());
+    assert!(CipherKeys::parse("old", "old").is_err());
+    assert!(CipherKeys::parse("old:not base64", "old").is_err());
+    assert!(CipherKeys::parse("old:AAAA", "old").is_err());
+  }
+}
@@ -1,6 +1,7 @@
 #![allow(dead_code)]
 
 use data_encoding::BASE32;
+use hmac::{Hmac, Mac};
 use sha2::{Digest, Sha256};
 use rand::{distributions::Alphanumeric, Rng};
 
@@ -19,6 +20,48 @@ impl HasherService {
     hex::encode(result)
   }
 
+  // генерирует HMAC-SHA256 (hex)
+  pub fn hmac_sha256(
+    key: &[u8],
+    value: &str
+  ) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key)
+      .expect("HMAC accepts keys of any length");
+    mac.update(value.as_bytes());
+
+    hex::encode(mac.finalize().into_bytes())
+  }
+
+  // сравнивает HMAC-SHA256 за постоянное время
+  pub fn verify_hmac_sha256(
+    key: &[u8],
+    value: &str,
+    expected: &str
+  ) -> bool {
+    let Ok(expected) = hex::decode(expected) else {
+      return false;
+    };
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(key)
+      .expect("HMAC accepts keys of any length");
+    mac.update(value.as_bytes());
+
+    mac.verify_slice(&expected).is_ok()
+  }
+
+  // сравнивает строки за постоянное время (например, секреты клиентов)
+  pub fn secure_eq(
+    a: &str,
+    b: &str
+  ) -> bool {
+    let a = Sha256::digest(a.as_bytes());
+    let b = Sha256::digest(b.as_bytes());
+
+    a.iter()
+      .zip(b.iter())
+      .fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+  }
+
   // генерирует соль для пароля
   pub fn generate_salt() -> String {
     rand::thread_rng()
@@ -37,6 +80,17 @@ impl HasherService {
       .collect()
   }
 
+  // генерирует резервный код для 2FA (вида xxxxx-xxxxx)
+  pub fn generate_backup_code() -> String {
+    let code = rand::thread_rng()
+      .sample_iter(&Alphanumeric)
+      .take(10)
+      .map(|c| char::from(c).to_ascii_lowercase())
+      .collect::<String>();
+
+    format!("{}-{}", &code[..5], &code[5..])
+  }
+
   pub fn generate_2fa_secret() -> String {
     let mut rng = rand::thread_rng();
     let mut bytes = [0u8; 20];
@@ -0,0 +1,110 @@
+use axum::Json;
+use adjust::{database::{postgres::Postgres, redis::Redis, Database}, response::HttpResult};
+use serde::Serialize;
+use crate::repository::{auth::AuthRepository, session::SessionRepository};
+use super::{jwt::{Claims, JWTService, TokenKind}, revocation::RevocationService, session::SessionService};
+
+/// Ответ ``/introspect`` (RFC 7662)
+///
+/// Для неактивного токена отдаётся только ``{"active": false}``.
+#[derive(Serialize, Default)]
+pub struct IntrospectionResponse {
+  pub active: bool,
+  /// Ранг пользователя (только у access токенов)
+  ///
+  /// Это не OAuth scope: других scope у токенов нет, а ``client_id`` не отдаётся,
+  /// потому что токены выдаются пользователям, а не OAuth клиентам.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub scope: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub username: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub token_type: Option<TokenKind>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub exp: Option<usize>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub iat: Option<usize>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub sub: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub aud: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub iss: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub jti: Option<String>,
+  /// Айди сессии
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub sid: Option<i32>,
+  /// Айди пользователя в сервисе user
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub user_id: Option<i32>,
+}
+
+pub struct IntrospectionService;
+
+impl IntrospectionService {
+  // проверяет токен и его сессию
+  // любые ошибки (невалидная подпись, истёкший токен, завершённая сессия)
+  // по RFC 7662 превращаются в ``active: false``
+  pub fn introspect(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    token: &str
+  ) -> HttpResult<IntrospectionResponse> {
+    Ok(Json(Self::inspect(db, redis, token)
+      .unwrap_or_default()))
+  }
+
+  fn inspect(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    token: &str
+  ) -> Option<IntrospectionResponse> {
+    let Json(data) = JWTService::decode_any(token).ok()?;
+    let claims = data.claims;
+
+    if RevocationService::is_revoked(redis, &claims).ok()? {
+      return None;
+    }
+
+    // /introspect только читает сессию, поэтому истёкшую не завершаем
+    let session = SessionRepository::find(db, claims.sid).ok()?;
+
+    if !session.is_active || SessionService::is_expired(&session) {
+      return None;
+    }
+
+    // refresh и launcher токены одноразовые: уже использованный токен не активен
+    if let Some(generation) = claims.gen {
+      if !SessionService::is_current_token(&session, generation, token) {
+        return None;
+      }
+    }
+
+    let username = AuthRepository::find(db, session.user_id)
+      .ok()?
+      .username;
+
+    Some(Self::response(claims, username))
+  }
+
+  fn response(
+    claims: Claims,
+    username: String
+  ) -> IntrospectionResponse {
+    IntrospectionResponse {
+      active: true,
+      scope: claims.rank,
+      username: Some(username),
+      token_type: Some(claims.typ),
+      exp: Some(claims.exp),
+      iat: Some(claims.iat),
+      sub: Some(claims.sub),
+      aud: Some(claims.aud),
+      iss: Some(claims.iss),
+      jti: Some(claims.jti),
+      sid: Some(claims.sid),
+      user_id: Some(claims.user_id),
+    }
+  }
+}
@@ -2,23 +2,60 @@
 
 use anyhow::{anyhow, Result};
 use axum::Json;
-use jsonwebtoken::{encode, decode, Header, Validation, EncodingKey, DecodingKey, Algorithm, TokenData};
+use jsonwebtoken::{encode, decode, decode_header, Header, Validation, TokenData};
 use reqwest::StatusCode;
 use serde::{Serialize, Deserialize};
 use adjust::response::{HttpError, HttpResult};
 
-use super::time::TimeService;
+use crate::{misc::env_or, models::Session};
+use super::{hasher::HasherService, keystore::KeyStore, time::TimeService};
 
 lazy_static::lazy_static! {
-  static ref JWT_SECRET: String = std::env::var("JWT_SECRET")
-    .expect("The JWT_SECRET environment variable was not found!");
+  // кто выпустил токен (claim ``iss``)
+  static ref JWT_ISSUER: String = std::env::var("JWT_ISSUER")
+    .unwrap_or(String::from("riverfallmc.ru"));
+  // для кого выпущен токен (claim ``aud``)
+  static ref JWT_AUDIENCE: String = std::env::var("JWT_AUDIENCE")
+    .unwrap_or(String::from("riverfallmc"));
+  // сроки жизни токенов (в минутах)
+  static ref ACCESS_LIFETIME: usize = env_or("JWT_ACCESS_LIFETIME", 60);
+  static ref REFRESH_LIFETIME: usize = env_or("JWT_REFRESH_LIFETIME", 60 * 24 * 7);
+  static ref REMEMBER_REFRESH_LIFETIME: usize = env_or("JWT_REMEMBER_REFRESH_LIFETIME", 60 * 24 * 30);
+}
+
+/// Тип токена (claim ``typ``)
+///
+/// Токен одного типа нельзя использовать там, где ожидается другой
+/// (например, refresh токен вместо access токена).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenKind {
+  Access,
+  Refresh,
+  Launcher
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
-  sub: String,
-  exp: usize,
-  refresh: bool,
+  pub iss: String,
+  pub aud: String,
+  /// Айди пользователя в сервисе auth (``users.id``)
+  pub sub: String,
+  pub iat: usize,
+  pub exp: usize,
+  /// Уникальный айди токена
+  pub jti: String,
+  pub typ: TokenKind,
+  /// Айди сессии (``sessions.id``)
+  pub sid: i32,
+  /// Айди пользователя в сервисе user (``users.user_id``)
+  pub user_id: i32,
+  /// Ранг пользователя из сервиса user (только у access токенов)
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub rank: Option<String>,
+  /// Номер refresh токена в семействе (только у refresh токенов)
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub gen: Option<i32>,
 }
 
 pub struct JWTService;
@@ -33,12 +70,7 @@ impl JWTService {
       return false; // Неправильный формат JWT
     }
 
-    decode::<Claims>(
-      token,
-      &DecodingKey::from_secret(JWT_SECRET.as_bytes()),
-      &Validation::new(Algorithm::HS256),
-    )
-    .is_ok()
+    Self::verify(token).is_ok()
   }
 
   pub fn is_active(
@@ -48,7 +80,7 @@ impl JWTService {
       return Err(HttpError::new("Невалидный токен", Some(StatusCode::UNAUTHORIZED)))
     }
 
-    let payload = Self::decode_token(&token)?;
+    let payload = Self::decode(&token, TokenKind::Access)?;
 
     if payload.claims.exp < (TimeService::get_current_timestamp() as usize) {
       return Err(HttpError::new("Токен истёк", Some(StatusCode::UNAUTHORIZED)))
@@ -57,50 +89,187 @@ impl JWTService {
     Ok(axum::Json(payload.claims.sub.clone()))
   }
 
-  // генерация jwt (действует 1 час)
+  // заполняет общие для всех токенов claims
+  fn claims(
+    session: &Session,
+    minutes: usize,
+    typ: TokenKind
+  ) -> Claims {
+    Claims {
+      iss: JWT_ISSUER.clone(),
+      aud: JWT_AUDIENCE.clone(),
+      sub: session.user_id.to_string(),
+      iat: TimeService::get_current_timestamp() as usize,
+      exp: Self::calculate_exp(minutes),
+      jti: HasherService::generate_code(),
+      typ,
+      sid: session.id,
+      user_id: session.global_id,
+      rank: None,
+      gen: None,
+    }
+  }
+
+  // срок жизни access токена (в минутах)
+  pub fn access_lifetime() -> usize {
+    *ACCESS_LIFETIME
+  }
+
+  // срок жизни refresh токена (в минутах)
+  // с "запомнить меня" выдаём более долгий
+  pub fn refresh_lifetime(
+    remember: bool
+  ) -> usize {
+    if remember {
+      *REMEMBER_REFRESH_LIFETIME
+    } else {
+      *REFRESH_LIFETIME
+    }
+  }
+
+  // наибольший срок жизни токена сессии (в минутах)
+  // refresh и launcher токены живут не дольше refresh_lifetime
+  pub fn max_lifetime() -> usize {
+    Self::access_lifetime()
+      .max(Self::refresh_lifetime(false))
+      .max(Self::refresh_lifetime(true))
+  }
+
+  // генерация jwt (по умолчанию действует 1 час)
   pub fn generate(
-    user_id: i32
+    session: &Session,
+    rank: String
   ) -> Result<String> {
     let claims = Claims {
-      sub: user_id.to_string(),
-      exp: Self::calculate_exp(60),
-      refresh: false,
+      rank: Some(rank),
+      ..Self::claims(session, *ACCESS_LIFETIME, TokenKind::Access)
     };
 
-    encode(
-      &Header::new(Algorithm::HS256),
-      &claims,
-      &EncodingKey::from_secret(JWT_SECRET.as_bytes()),
-    ).map_err(|_| anyhow!("Не получилось сгенерировать JWT"))
+    Self::sign(&claims)
+      .map_err(|_| anyhow!("Не получилось сгенерировать JWT"))
   }
 
-  // генерация refresh токена (действует 7 дней)
+  // генерация refresh токена, действующего minutes минут
   pub fn generate_refresh(
-    user_id: i32
+    session: &Session,
+    generation: i32,
+    minutes: usize
+  ) -> Result<String> {
+    let claims = Claims {
+      gen: Some(generation),
+      ..Self::claims(session, minutes, TokenKind::Refresh)
+    };
+
+    Self::sign(&claims)
+      .map_err(|_| anyhow!("Не получилось сгенерировать Refresh токен"))
+  }
+
+  // генерация токена лаунчера (accessToken в Yggdrasil), действующего minutes минут
+  pub fn generate_launcher(
+    session: &Session,
+    generation: i32,
+    minutes: usize
   ) -> Result<String> {
     let claims = Claims {
-      sub: user_id.to_string(),
-      exp: Self::calculate_exp(60 * 24 * 7),
-      refresh: true,
+      gen: Some(generation),
+      ..Self::claims(session, minutes, TokenKind::Launcher)
     };
 
-    encode(
-      &Header::new(Algorithm::HS256),
-      &claims,
-      &EncodingKey::from_secret(JWT_SECRET.as_bytes()),
-    ).map_err(|_| anyhow!("Не получилось сгенерировать Refresh токен"))
+    Self::sign(&claims)
+      .map_err(|_| anyhow!("Не получилось сгенерировать токен лаунчера"))
+  }
+
+  // декодирование токена ожидаемого типа и возврат данных
+  pub fn decode(
+    token: &str,
+    kind: TokenKind
+  ) -> HttpResult<TokenData<Claims>> {
+    Self::decode_with(KeyStore::get(), token, kind)
+  }
+
+  fn decode_with(
+    keys: &KeyStore,
+    token: &str,
+    kind: TokenKind
+  ) -> HttpResult<TokenData<Claims>> {
+    let data = Self::verify_with(keys, token)
+      .map_err(|_| HttpError::new("Невалидный или истёкший токен", Some(StatusCode::UNAUTHORIZED)))?;
+
+    if data.claims.typ != kind {
+      return Err(HttpError::new("Неверный тип токена", Some(StatusCode::UNAUTHORIZED)));
+    }
+
+    Ok(Json(data))
   }
 
-  // декодирование токена и возврат данных
-  pub fn decode_token(
+  // декодирование токена любого типа (например, для /introspect)
+  pub fn decode_any(
     token: &str
   ) -> HttpResult<TokenData<Claims>> {
-    Ok(Json(decode::<Claims>(
-      token,
-      &DecodingKey::from_secret(JWT_SECRET.as_bytes()),
-      &Validation::new(Algorithm::HS256),
-    )
-    .map_err(|_| HttpError::new("Невалидный или истёкший токен", Some(StatusCode::UNAUTHORIZED)))?))
+    Ok(Json(Self::verify(token)
+      .map_err(|_| HttpError::new("Невалидный или истёкший токен", Some(StatusCode::UNAUTHORIZED)))?))
+  }
+
+  pub fn decode_access(
+    token: &str
+  ) -> HttpResult<TokenData<Claims>> {
+    Self::decode(token, TokenKind::Access)
+  }
+
+  pub fn decode_refresh(
+    token: &str
+  ) -> HttpResult<TokenData<Claims>> {
+    Self::decode(token, TokenKind::Refresh)
+  }
+
+  // подписывает claims текущим ключом (kid попадает в заголовок)
+  fn sign(
+    claims: &Claims
+  ) -> Result<String> {
+    Self::sign_with(KeyStore::get(), claims)
+  }
+
+  fn sign_with(
+    keys: &KeyStore,
+    claims: &Claims
+  ) -> Result<String> {
+    let key = keys.signing_key();
+
+    let mut header = Header::new(key.algorithm);
+    header.kid = Some(key.kid.clone());
+
+    let encoding = key.encoding.as_ref()
+      .ok_or(anyhow!("Ключ {} не может подписывать токены", key.kid))?;
+
+    Ok(encode(&header, claims, encoding)?)
+  }
+
+  // проверяет подпись ключом из заголовка kid, а также iss и aud
+  // алгоритм берётся из ключа, а не из заголовка токена
+  fn verify(
+    token: &str
+  ) -> Result<TokenData<Claims>> {
+    Self::verify_with(KeyStore::get(), token)
+  }
+
+  fn verify_with(
+    keys: &KeyStore,
+    token: &str
+  ) -> Result<TokenData<Claims>> {
+    let kid = decode_header(token)?
+      .kid
+      .ok_or(anyhow!("В токене отсутствует kid"))?;
+
+    let key = keys
+      .find(&kid)
+      .ok_or(anyhow!("Неизвестный ключ {kid}"))?;
+
+    let mut validation = Validation::new(key.algorithm);
+    validation.set_issuer(&[JWT_ISSUER.as_str()]);
+    validation.set_audience(&[JWT_AUDIENCE.as_str()]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+    Ok(decode::<Claims>(token, &key.decoding, &validation)?)
   }
 
   // вычисление времени истечения токена (в секундах)
@@ -110,4 +279,70 @@ impl JWTService {
     let now = TimeService::get_current_timestamp() as usize;
     now + minutes * 60
   }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+  use ed25519_dalek::{pkcs8::{spki::der::pem::LineEnding, EncodePrivateKey}, SigningKey};
+  use super::*;
+
+  fn keys() -> KeyStore {
+    let pem = SigningKey::from_bytes(&[7; 32])
+      .to_pkcs8_pem(LineEnding::LF)
+      .unwrap();
+
+    KeyStore::from_private_pem("test", &pem).unwrap()
+  }
+
+  fn token(
+    keys: &KeyStore,
+    typ: TokenKind
+  ) -> String {
+    let claims = Claims {
+      iss: JWT_ISSUER.clone(),
+      aud: JWT_AUDIENCE.clone(),
+      sub: String::from("1"),
+      iat: TimeService::get_current_timestamp() as usize,
+      exp: JWTService::calculate_exp(5),
+      jti: String::from("jti"),
+      typ,
+      sid: 1,
+      user_id: 1,
+      rank: None,
+      gen: None
+    };
+
+    JWTService::sign_with(keys, &claims).unwrap()
+  }
+
+  #[test]
+  fn accepts_token_of_expected_kind() {
+    let keys = keys();
+
+    for kind in [TokenKind::Access, TokenKind::Refresh, TokenKind::Launcher] {
+      assert_eq!(JWTService::decode_with(&keys, &token(&keys, kind), kind).unwrap().claims.typ, kind);
+    }
+  }
+
+  #[test]
+  fn rejects_refresh_token_as_access() {
+    let keys = keys();
+
+    assert!(JWTService::decode_with(&keys, &token(&keys, TokenKind::Refresh), TokenKind::Access).is_err());
+  }
+
+  #[test]
+  fn rejects_access_token_as_refresh() {
+    let keys = keys();
+
+    assert!(JWTService::decode_with(&keys, &token(&keys, TokenKind::Access), TokenKind::Refresh).is_err());
+  }
+
+  #[test]
+  fn rejects_launcher_token_as_access_and_refresh() {
+    let keys = keys();
+    let launcher = token(&keys, TokenKind::Launcher);
+
+    assert!(JWTService::decode_with(&keys, &launcher, TokenKind::Access).is_err());
+    assert!(JWTService::decode_with(&keys, &launcher, TokenKind::Refresh).is_err());
+  }
+}
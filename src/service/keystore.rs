@@ -0,0 +1,260 @@
+#![allow(dead_code)]
+
+use std::{env, fs, path::Path};
+use anyhow::{anyhow, bail, Context, Result};
+use data_encoding::BASE64URL_NOPAD;
+use hashbrown::HashMap;
+use jsonwebtoken::{jwk::{AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType}, Algorithm, DecodingKey, EncodingKey};
+use rsa::{pkcs1::DecodeRsaPrivateKey, pkcs8::{DecodePrivateKey, DecodePublicKey}, traits::PublicKeyParts, RsaPrivateKey, RsaPublicKey};
+
+lazy_static::lazy_static! {
+  static ref KEYSTORE: KeyStore = KeyStore::load()
+    .expect("Unable to load JWT signing keys!");
+}
+
+// суффикс файлов с публичными ключами
+// (ключи, которыми мы больше не подписываем, но ещё проверяем токены)
+const PUBLIC_SUFFIX: &str = ".pub.pem";
+const PRIVATE_SUFFIX: &str = ".pem";
+
+/// Ключ для подписи/проверки JWT
+pub struct JwtKey {
+  pub kid: String,
+  pub algorithm: Algorithm,
+  /// ``None`` - ключ выведен из ротации и используется только для проверки
+  pub encoding: Option<EncodingKey>,
+  pub decoding: DecodingKey,
+  pub jwk: Jwk
+}
+
+/// Хранилище ключей JWT
+///
+/// Ключи загружаются из директории ``JWT_KEYS_DIR``:
+/// * ``<kid>.pem`` - приватный ключ (PKCS#8/PKCS#1 RSA или PKCS#8 Ed25519)
+/// * ``<kid>.pub.pem`` - публичный ключ (только для проверки старых токенов)
+///
+/// Подписываем ключом ``JWT_SIGNING_KID``, а если он не указан -
+/// приватным ключом с наибольшим (лексикографически) kid.
+pub struct KeyStore {
+  keys: HashMap<String, JwtKey>,
+  signing: String
+}
+
+impl KeyStore {
+  pub fn get() -> &'static KeyStore {
+    &KEYSTORE
+  }
+
+  fn load() -> Result<KeyStore> {
+    let dir = env::var("JWT_KEYS_DIR")
+      .unwrap_or(String::from("data/keys"));
+
+    let mut keys = HashMap::new();
+
+    for entry in fs::read_dir(&dir).with_context(|| format!("Unable to read {dir}"))? {
+      let path = entry?.path();
+
+      if let Some(key) = Self::load_key(&path)? {
+        Self::insert(&mut keys, key)?;
+      }
+    }
+
+    for key in keys.values() {
+      log::info!("loaded JWT key {} ({:?})", key.kid, key.algorithm);
+    }
+
+    Self::from_keys(keys, env::var("JWT_SIGNING_KID").ok())
+      .with_context(|| format!("Invalid JWT keys in {dir}"))
+  }
+
+  // собирает хранилище из уже загруженных ключей
+  // signing - kid ключа для подписи (None - приватный ключ с наибольшим kid)
+  fn from_keys(
+    keys: HashMap<String, JwtKey>,
+    signing: Option<String>
+  ) -> Result<KeyStore> {
+    let signing = match signing {
+      Some(kid) => kid,
+      None => keys.values()
+        .filter(|key| key.encoding.is_some())
+        .map(|key| key.kid.clone())
+        .max()
+        .ok_or(anyhow!("No private keys found"))?
+    };
+
+    match keys.get(&signing) {
+      Some(key) if key.encoding.is_some() => {},
+      _ => bail!("Private key {signing} not found")
+    }
+
+    Ok(KeyStore { keys, signing })
+  }
+
+  // хранилище из одного приватного ключа (для тестов)
+  #[cfg(test)]
+  pub fn from_private_pem(
+    kid: &str,
+    pem: &str
+  ) -> Result<KeyStore> {
+    let key = Self::parse_private(kid, pem)?;
+
+    Self::from_keys(HashMap::from([(kid.to_owned(), key)]), None)
+  }
+
+  // ``<kid>.pub.pem`` рядом с ``<kid>.pem`` не нужен (публичный ключ выводится из приватного),
+  // поэтому его пропускаем, а любые другие повторы kid - ошибка
+  fn insert(
+    keys: &mut HashMap<String, JwtKey>,
+    key: JwtKey
+  ) -> Result<()> {
+    match keys.get(&key.kid) {
+      None => {},
+      Some(existing) if existing.encoding.is_some() && key.encoding.is_none() => return Ok(()),
+      Some(existing) if existing.encoding.is_none() && key.encoding.is_some() => {},
+      Some(_) => bail!("Duplicate JWT key {}", key.kid)
+    }
+
+    keys.insert(key.kid.clone(), key);
+
+    Ok(())
+  }
+
+  fn load_key(
+    path: &Path
+  ) -> Result<Option<JwtKey>> {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+      return Ok(None);
+    };
+
+    let pem = fs::read_to_string(path)?;
+
+    if let Some(kid) = name.strip_suffix(PUBLIC_SUFFIX) {
+      return Self::parse_public(kid, &pem).map(Some);
+    }
+
+    if let Some(kid) = name.strip_suffix(PRIVATE_SUFFIX) {
+      return Self::parse_private(kid, &pem).map(Some);
+    }
+
+    Ok(None)
+  }
+
+  fn parse_private(
+    kid: &str,
+    pem: &str
+  ) -> Result<JwtKey> {
+    let rsa = RsaPrivateKey::from_pkcs8_pem(pem)
+      .or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem));
+
+    if let Ok(private) = rsa {
+      let encoding = EncodingKey::from_rsa_pem(pem.as_bytes())?;
+
+      return Self::rsa_key(kid, &RsaPublicKey::from(&private), Some(encoding));
+    }
+
+    if let Ok(private) = ed25519_dalek::SigningKey::from_pkcs8_pem(pem) {
+      let encoding = EncodingKey::from_ed_pem(pem.as_bytes())?;
+
+      return Self::ed_key(kid, &private.verifying_key(), Some(encoding));
+    }
+
+    bail!("Unsupported private key {kid}")
+  }
+
+  fn parse_public(
+    kid: &str,
+    pem: &str
+  ) -> Result<JwtKey> {
+    if let Ok(public) = RsaPublicKey::from_public_key_pem(pem) {
+      return Self::rsa_key(kid, &public, None);
+    }
+
+    if let Ok(public) = ed25519_dalek::VerifyingKey::from_public_key_pem(pem) {
+      return Self::ed_key(kid, &public, None);
+    }
+
+    bail!("Unsupported public key {kid}")
+  }
+
+  fn rsa_key(
+    kid: &str,
+    public: &RsaPublicKey,
+    encoding: Option<EncodingKey>
+  ) -> Result<JwtKey> {
+    let jwk = Jwk {
+      common: Self::common(kid, KeyAlgorithm::RS256),
+      algorithm: AlgorithmParameters::RSA(RSAKeyParameters {
+        key_type: RSAKeyType::RSA,
+        n: BASE64URL_NOPAD.encode(&public.n().to_bytes_be()),
+        e: BASE64URL_NOPAD.encode(&public.e().to_bytes_be())
+      })
+    };
+
+    Ok(JwtKey {
+      kid: kid.to_owned(),
+      algorithm: Algorithm::RS256,
+      decoding: DecodingKey::from_jwk(&jwk)?,
+      encoding,
+      jwk
+    })
+  }
+
+  fn ed_key(
+    kid: &str,
+    public: &ed25519_dalek::VerifyingKey,
+    encoding: Option<EncodingKey>
+  ) -> Result<JwtKey> {
+    let jwk = Jwk {
+      common: Self::common(kid, KeyAlgorithm::EdDSA),
+      algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+        key_type: OctetKeyPairType::OctetKeyPair,
+        curve: EllipticCurve::Ed25519,
+        x: BASE64URL_NOPAD.encode(public.as_bytes())
+      })
+    };
+
+    Ok(JwtKey {
+      kid: kid.to_owned(),
+      algorithm: Algorithm::EdDSA,
+      decoding: DecodingKey::from_jwk(&jwk)?,
+      encoding,
+      jwk
+    })
+  }
+
+  fn common(
+    kid: &str,
+    algorithm: KeyAlgorithm
+  ) -> CommonParameters {
+    CommonParameters {
+      public_key_use: Some(PublicKeyUse::Signature),
+      key_algorithm: Some(algorithm),
+      key_id: Some(kid.to_owned()),
+      ..Default::default()
+    }
+  }
+
+  // ключ, которым подписываются новые токены
+  pub fn signing_key(&self) -> &JwtKey {
+    &self.keys[&self.signing]
+  }
+
+  // ищет ключ для проверки токена
+  pub fn find(
+    &self,
+    kid: &str
+  ) -> Option<&JwtKey> {
+    self.keys.get(kid)
+  }
+
+  // публичные ключи в формате JWKS
+  pub fn jwks(&self) -> JwkSet {
+    let mut keys = self.keys.values()
+      .map(|key| key.jwk.clone())
+      .collect::<Vec<Jwk>>();
+
+    keys.sort_by(|a, b| a.common.key_id.cmp(&b.common.key_id));
+
+    JwkSet { keys }
+  }
+}
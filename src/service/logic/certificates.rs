@@ -0,0 +1,222 @@
+use adjust::database::{postgres::Postgres, redis::Redis, Database};
+use axum::Json;
+use chrono::{DateTime, SecondsFormat, TimeDelta, Utc};
+use data_encoding::BASE64_MIME;
+use rsa::{pkcs8::{EncodePrivateKey, EncodePublicKey}, RsaPrivateKey, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use crate::{misc::env_or, models::Session, repository::auth::AuthRepository, service::{redis::RedisService, yggdrasil_key::YggdrasilKey}};
+use super::yggdrasil::{YggdrasilError, YggdrasilResult, YggdrasilService};
+
+lazy_static::lazy_static! {
+  // сколько живёт ключ игрока, в минутах
+  static ref PLAYER_CERTIFICATE_LIFETIME: i64 = env_or("PLAYER_CERTIFICATE_LIFETIME", 2880);
+}
+
+// размер ключа игрока (как у Mojang)
+const PLAYER_KEY_BITS: usize = 2048;
+
+/// Ключи игрока для подписи чата (Minecraft 1.19+)
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerCertificates {
+  pub key_pair: PlayerKeyPair,
+  /// Подпись для 1.19
+  pub public_key_signature: String,
+  /// Подпись для 1.19.1+
+  pub public_key_signature_v2: String,
+  pub expires_at: String,
+  /// После этого момента клиент запросит новые ключи
+  pub refreshed_after: String
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerKeyPair {
+  pub private_key: String,
+  pub public_key: String
+}
+
+/// Выдача ключей игрока (``/player/certificates``)
+///
+/// Публичный ключ игрока вместе со сроком действия подписывается ключом Yggdrasil,
+/// поэтому сервер может проверить его так же, как свойства профиля.
+/// Ключи кэшируются в Redis на сессию лаунчера, пока клиенту не пора их обновить.
+pub struct CertificatesService;
+
+impl CertificatesService {
+  fn generate_key(
+    session_id: i32
+  ) -> String {
+    format!("yggdrasil:certificates:{}", session_id)
+  }
+
+  pub async fn get(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    access_token: &str
+  ) -> YggdrasilResult<PlayerCertificates> {
+    let session = YggdrasilService::find_current_session(db, redis, access_token, None)?;
+    let key = Self::generate_key(session.id);
+
+    let cached = RedisService::get::<Option<String>>(redis, &key)
+      .map_err(YggdrasilError::internal)?
+      .and_then(|record| serde_json::from_str::<PlayerCertificates>(&record).ok());
+
+    if let Some(certificates) = cached {
+      return Ok(Json(certificates));
+    }
+
+    let (certificates, lifetime) = Self::issue(db, &session)
+      .await?;
+
+    let record = serde_json::to_string(&certificates)
+      .map_err(YggdrasilError::internal)?;
+
+    RedisService::set_temporarily_secs(redis, &key, record, lifetime)
+      .map_err(YggdrasilError::internal)?;
+
+    Ok(Json(certificates))
+  }
+
+  // создаёт новую пару ключей и подписывает её
+  // возвращает ключи и сколько секунд их можно отдавать из кэша
+  async fn issue(
+    db: &mut Database<Postgres>,
+    session: &Session
+  ) -> Result<(PlayerCertificates, u64), YggdrasilError> {
+    let user = AuthRepository::find(db, session.user_id)
+      .map_err(|_| YggdrasilError::invalid_token())?;
+
+    let uuid = hex::decode(&user.uuid)
+      .map_err(YggdrasilError::internal)?;
+
+    // генерация RSA ключа занимает десятки и сотни миллисекунд процессора,
+    // поэтому уводим её с воркеров tokio, чтобы не тормозить остальные запросы
+    let private = tokio::task::spawn_blocking(|| RsaPrivateKey::new(&mut rand::thread_rng(), PLAYER_KEY_BITS))
+      .await
+      .map_err(YggdrasilError::internal)?
+      .map_err(YggdrasilError::internal)?;
+
+    let private_der = private.to_pkcs8_der()
+      .map_err(YggdrasilError::internal)?;
+    let public_der = RsaPublicKey::from(&private).to_public_key_der()
+      .map_err(YggdrasilError::internal)?;
+
+    let lifetime = TimeDelta::minutes(*PLAYER_CERTIFICATE_LIFETIME);
+    let now = Utc::now();
+    let expires_at = now + lifetime;
+    // как у Mojang: обновляем за шестую часть срока до истечения
+    let refreshed_after = expires_at - lifetime / 6;
+
+    let public_key = Self::pem("RSA PUBLIC KEY", public_der.as_bytes());
+    let expires_millis = expires_at.timestamp_millis();
+
+    let signature_v1 = Self::payload_v1(expires_millis, &public_key);
+    let signature_v2 = Self::payload_v2(&uuid, expires_millis, public_der.as_bytes());
+
+    let key = YggdrasilKey::get();
+
+    let certificates = PlayerCertificates {
+      key_pair: PlayerKeyPair {
+        private_key: Self::pem("RSA PRIVATE KEY", private_der.as_bytes()),
+        public_key
+      },
+      public_key_signature: key.sign(signature_v1.as_bytes()),
+      public_key_signature_v2: key.sign(&signature_v2),
+      expires_at: Self::format_time(expires_at),
+      refreshed_after: Self::format_time(refreshed_after)
+    };
+
+    let cache_lifetime = (refreshed_after - now).num_seconds().max(1) as u64;
+
+    Ok((certificates, cache_lifetime))
+  }
+
+  // что подписывается для 1.19: миллисекунды + PEM публичного ключа
+  fn payload_v1(
+    expires_millis: i64,
+    public_pem: &str
+  ) -> String {
+    format!("{expires_millis}{public_pem}")
+  }
+
+  // что подписывается для 1.19.1+: UUID игрока + миллисекунды (big-endian) + DER публичного ключа
+  fn payload_v2(
+    uuid: &[u8],
+    expires_millis: i64,
+    public_der: &[u8]
+  ) -> Vec<u8> {
+    let mut payload = uuid.to_vec();
+    payload.extend_from_slice(&expires_millis.to_be_bytes());
+    payload.extend_from_slice(public_der);
+
+    payload
+  }
+
+  // PEM в том виде, в котором его собирает клиент Minecraft
+  // (MIME base64 по 76 символов в строке)
+  fn pem(
+    label: &str,
+    der: &[u8]
+  ) -> String {
+    let body = BASE64_MIME.encode(der).replace("\r\n", "\n");
+
+    format!("-----BEGIN {label}-----\n{}\n-----END {label}-----\n", body.trim_end())
+  }
+
+  fn format_time(
+    time: DateTime<Utc>
+  ) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Micros, true)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const UUID: &str = "b50ad385829d3141a2167e7d7539ba7f";
+
+  #[test]
+  fn builds_v1_payload() {
+    let pem = CertificatesService::pem("RSA PUBLIC KEY", &[1, 2, 3]);
+
+    assert_eq!(CertificatesService::payload_v1(1700000000000, &pem), format!("1700000000000{pem}"));
+  }
+
+  #[test]
+  fn builds_v2_payload() {
+    let uuid = hex::decode(UUID).unwrap();
+    let payload = CertificatesService::payload_v2(&uuid, 0x0102030405060708, &[0xaa, 0xbb]);
+
+    assert_eq!(payload.len(), 16 + 8 + 2);
+    assert_eq!(&payload[..16], uuid.as_slice());
+    assert_eq!(&payload[16..24], &[1, 2, 3, 4, 5, 6, 7, 8]);
+    assert_eq!(&payload[24..], &[0xaa, 0xbb]);
+  }
+
+  #[test]
+  fn formats_pem_like_minecraft() {
+    let der = (0..=255).collect::<Vec<u8>>();
+    let pem = CertificatesService::pem("RSA PUBLIC KEY", &der);
+
+    assert!(pem.starts_with("-----BEGIN RSA PUBLIC KEY-----\n"));
+    assert!(pem.ends_with("\n-----END RSA PUBLIC KEY-----\n"));
+    assert!(!pem.contains('\r'));
+
+    let body = pem.lines()
+      .filter(|line| !line.starts_with("-----"))
+      .collect::<Vec<&str>>();
+
+    assert!(body.iter().all(|line| line.len() <= 76));
+    assert_eq!(body[0].len(), 76);
+    assert_eq!(BASE64_MIME.decode(body.concat().as_bytes()).unwrap(), der);
+  }
+
+  #[test]
+  fn formats_time_as_rfc3339() {
+    let time = DateTime::from_timestamp_millis(1700000000123).unwrap();
+
+    assert_eq!(CertificatesService::format_time(time), "2023-11-14T22:13:20.123000Z");
+  }
+}
@@ -5,4 +5,8 @@ pub mod register;
 /// Восстановление пароля
 pub mod recovery;
 /// Двуфакторная аутентификация
-pub mod tfa;
\ No newline at end of file
+pub mod tfa;
+/// Ключи игрока для подписи чата
+pub mod certificates;
+/// Yggdrasil (авторизация из лаунчера Minecraft)
+pub mod yggdrasil;
\ No newline at end of file
@@ -2,7 +2,7 @@ use anyhow::Result;
 use axum::Json;
 use adjust::{database::{postgres::Postgres, redis::Redis, Database}, response::{HttpError, HttpMessage, HttpResult}};
 use reqwest::StatusCode;
-use crate::{models::UserPasswordUpdate, repository::{auth::AuthRepository, user::UserRepository}, service::{authvalidate::AuthValidateService, hasher::HasherService, mail::{mails::recovery::RecoveryMail, service::MailService}, redis::RedisService}};
+use crate::{models::UserPasswordUpdate, repository::{auth::AuthRepository, user::UserRepository}, service::{authvalidate::AuthValidateService, hasher::HasherService, mail::{mails::recovery::RecoveryMail, service::MailService}, owner_cache::OwnerCacheService, password::PasswordService, redis::RedisService}};
 
 pub struct RecoveryService;
 
@@ -98,10 +98,11 @@ impl RecoveryService {
       .await?;
 
     let user = AuthRepository::find(db, userdata.id)?;
-    let salt = HasherService::generate_salt();
-    let password = HasherService::sha256(password + &salt);
+    // соль хранится внутри PHC-строки, так что колонку salt чистим
+    let password = PasswordService::hash(&password)?;
 
-    AuthRepository::update(db, user.id, UserPasswordUpdate { salt, password })?;
+    AuthRepository::update(db, user.id, UserPasswordUpdate { salt: String::new(), password })?;
+    OwnerCacheService::invalidate(redis, user.user_id)?;
 
     Self::remove_record(redis, email.to_string())?;
 
@@ -1,4 +1,4 @@
-use crate::{models::{UserAdd, UserRegister}, repository::{auth::AuthRepository, user::UserRepository}, service::{authvalidate::AuthValidateService, hasher::HasherService, mail::{mails::register::RegisterMail, service::MailService}, redis::RedisService}};
+use crate::{models::{UserAdd, UserRegister}, repository::{auth::AuthRepository, user::UserRepository}, service::{authvalidate::AuthValidateService, hasher::HasherService, mail::{mails::register::RegisterMail, service::MailService}, password::PasswordService, redis::RedisService, uuid::UuidService}};
 use adjust::{database::{postgres::Postgres, redis::Redis, Database}, response::{HttpError, HttpMessage, HttpResult}};
 use reqwest::StatusCode;
 use axum::Json;
@@ -29,9 +29,10 @@ impl RegisterService {
 
     // ʕ•́ᴥ•̀ʔっ подготавливаем пользователя для хранения в редисе
     // оверрайдим значение (по идее оно вообще не должно быть документировано) поля salt
-    user.salt = Some(HasherService::generate_salt());
+    // соль теперь хранится внутри PHC-строки Argon2id
+    user.salt = None;
     // хэшируем пароль
-    user.password = HasherService::sha256(user.password + &user.salt.clone().unwrap());
+    user.password = PasswordService::hash(&user.password)?;
 
     // Отправляем письмо пользователю
     let (code, reg_id) = Self::generate_redis_confirm_key(None);
@@ -71,6 +72,11 @@ impl RegisterService {
     let mut user_to_add: UserAdd = user.clone().into();
     user_to_add.user_id = Some(user_row.id);
 
+    // офлайн UUID может быть занят аккаунтом, который раньше носил этот ник
+    if AuthRepository::find_by_uuid(db, &user_to_add.uuid).is_ok() {
+      user_to_add.uuid = UuidService::random();
+    }
+
     // добавляем юзера в наш бд
     #[allow(unused)]
     AuthRepository::add(db, &user_to_add)?;
@@ -1,26 +1,69 @@
-use crate::{controller::tfa::TFAAddBody, models::User, repository::auth::AuthRepository, service::{hasher::HasherService, redis::RedisService, session::SessionService }};
+use crate::{controller::tfa::TFAAddBody, models::{SessionWithTokens, User}, repository::{auth::AuthRepository, user::UserRepository}, service::{hasher::HasherService, mail::{email::Email, mails::{tfa_disabled::TFADisabledMail, tfa_reset::TFAResetMail}, service::MailService}, cipher::CipherService, password::PasswordService, redis::RedisService, session::SessionService, totp::TOTPService }};
 use adjust::{database::{postgres::Postgres, redis::Redis, Database}, response::{HttpError, HttpMessage, HttpResult, NonJsonHttpResult}};
 use serde::{Deserialize, Serialize};
 use reqwest::StatusCode;
 use totp_rs::TOTP;
 use axum::Json;
 
+/// Одноразовый тикет, который выдаётся после проверки пароля
+/// и нужен для второго шага авторизации
 #[derive(Serialize, Deserialize)]
-pub struct TwoFactorResponse {
-  pub secret: String,
-  pub qr: String
+pub struct LoginTicket {
+  pub user_id: i32,
+  pub useragent: String,
+  /// "Запомнить меня" из запроса на /login
+  #[serde(default)]
+  pub remember: bool,
+  /// Айди устройства из запроса на /login
+  #[serde(default)]
+  pub device_id: Option<String>
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct LoginTicketResponse {
+  pub message: String,
+  pub ticket: String
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BackupCodesResponse {
+  pub message: String,
+  /// Показываются пользователю один раз, в бд хранятся только хэши
+  pub backup_codes: Vec<String>
+}
+
+#[derive(Serialize)]
+pub struct TFALoginResponse {
+  #[serde(flatten)]
+  pub session: SessionWithTokens,
+  /// Сколько резервных кодов осталось (если вход был по резервному коду)
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub backup_codes_left: Option<usize>
+}
+
+// сколько резервных кодов выдаётся
+const BACKUP_CODES_COUNT: usize = 10;
+// сколько живёт незаконченная привязка 2FA (в минутах)
+const PENDING_LIFETIME: u64 = 10;
+// сколько живёт тикет (в минутах)
+const TICKET_LIFETIME: u64 = 5;
+// сколько раз можно ввести неверный код по одному тикету
+const MAX_ATTEMPTS: u64 = 5;
+// префикс хэшей резервных кодов, посчитанных HMAC с серверным ключом
+// (хэши без префикса - старые коды, захэшированные SHA-256 без ключа)
+const BACKUP_CODE_HMAC_PREFIX: &str = "hmac:";
+
 pub struct TFAService;
 
 impl TFAService {
   /// Генерирует TFA Secret
+  ///
+  /// Секрет хранится в редисе, пока пользователь не подтвердит привязку через ``link``.
   pub fn add(
     db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
     user_id: i32
   ) -> HttpResult<TFAAddBody> {
-    // TODO @ Въебашить редис
-
     let user = AuthRepository::find(db, user_id)?;
 
     if user.totp_secret.is_some() {
@@ -29,6 +72,9 @@ impl TFAService {
 
     let axum::Json((secret, totp)) = Self::generate_2fa(user.username, None)?;
 
+    // повторный вызов перезаписывает незаконченную привязку
+    RedisService::set_temporarily(redis, &Self::generate_redis_pending_key(user.id), &secret, PENDING_LIFETIME)?;
+
     Ok(Json(TFAAddBody {
       secret,
       qr: format!("data:image/png;base64,{}", totp.get_qr_base64().unwrap_or_default())
@@ -36,86 +82,401 @@ impl TFAService {
   }
 
   /// Привязка 2FA к профилю
+  ///
+  /// Принимает только код для секрета, выданного через ``add``.
   pub fn link(
     db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
     user_id: i32,
-    code: String,
-    secret: String
-  ) -> HttpResult<HttpMessage> {
+    code: String
+  ) -> HttpResult<BackupCodesResponse> {
     let user = AuthRepository::find(db, user_id)?;
 
+    if user.totp_secret.is_some() {
+      return Err(HttpError::new("К вашему аккаунту уже привязана двуфакторная аутентификация!", Some(StatusCode::BAD_REQUEST)))
+    }
+
+    let pending_key = Self::generate_redis_pending_key(user.id);
+
+    let secret = RedisService::get::<Option<String>>(redis, &pending_key)?
+      .ok_or(HttpError::new("Привязка двуфакторной аутентификации не начата (или истекла)", Some(StatusCode::BAD_REQUEST)))?;
+
     let axum::Json((_, totp)) = TFAService::generate_2fa(user.username.clone(), Some(secret.clone()))?;
 
-    if !totp.check_current(&code)? {
+    if !TOTPService::verify(redis, user.id, &totp, &code)? {
       return Err(HttpError::new("Неверный код", Some(StatusCode::UNAUTHORIZED)));
     }
 
-    AuthRepository::update_totp(db, user.id, secret)?;
+    AuthRepository::update_totp(db, user.id, CipherService::encrypt(&Self::secret_aad(user.id), &secret)?)?;
+    RedisService::remove(redis, &pending_key)?;
+
+    let backup_codes = Self::generate_backup_codes(db, user.id)?;
+
+    Ok(Json(BackupCodesResponse {
+      message: String::from("Двуфакторная аутентификация была привязана к вашему профилю. Сохраните резервные коды!"),
+      backup_codes
+    }))
+  }
+
+  /// Перевыпуск резервных кодов (старые перестают работать)
+  pub fn regenerate_backup_codes(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    user_id: i32,
+    code: String
+  ) -> HttpResult<BackupCodesResponse> {
+    let user = AuthRepository::find(db, user_id)?;
+
+    if user.totp_secret.is_none() {
+      return Err(HttpError::new("К вашему аккаунту не привязана двуфакторная аутентификация", Some(StatusCode::BAD_REQUEST)));
+    }
+
+    let axum::Json((_, totp)) = TFAService::generate_2fa(user.username.clone(), Some(Self::get_secret(&user)?))?;
+
+    if !TOTPService::verify(redis, user.id, &totp, &code)? {
+      return Err(HttpError::new("Неверный код", Some(StatusCode::UNAUTHORIZED)));
+    }
+
+    let backup_codes = Self::generate_backup_codes(db, user.id)?;
+
+    Ok(Json(BackupCodesResponse {
+      message: String::from("Резервные коды были перевыпущены"),
+      backup_codes
+    }))
+  }
+
+  /// Отключение 2FA самим пользователем
+  pub async fn disable(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    user_id: i32,
+    password: String,
+    code: String
+  ) -> HttpResult<HttpMessage> {
+    let user = AuthRepository::find(db, user_id)?;
+
+    if user.totp_secret.is_none() {
+      return Err(HttpError::new("К вашему аккаунту не привязана двуфакторная аутентификация", Some(StatusCode::BAD_REQUEST)));
+    }
+
+    if !PasswordService::verify(&password, &user.password, &user.salt) {
+      return Err(HttpError::new("Неверный пароль!", Some(StatusCode::UNAUTHORIZED)));
+    }
+
+    Self::verify_code(db, redis, &user, &code)?;
+
+    AuthRepository::remove_totp(db, user.id)?;
+
+    Self::notify(user.user_id, TFADisabledMail::new(user.username))
+      .await;
+
+    Ok(Json(HttpMessage::new("Двуфакторная аутентификация была отключена")))
+  }
+
+  /// Сброс 2FA администратором (например, если пользователь потерял телефон и коды)
+  ///
+  /// Завершает все сессии пользователя.
+  pub async fn reset(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    user_id: i32
+  ) -> HttpResult<HttpMessage> {
+    let user = AuthRepository::find(db, user_id)?;
+
+    if user.totp_secret.is_none() {
+      return Err(HttpError::new("К аккаунту не привязана двуфакторная аутентификация", Some(StatusCode::BAD_REQUEST)));
+    }
+
+    AuthRepository::remove_totp(db, user.id)?;
+    SessionService::delete_all(db, redis, user.id)?;
+
+    Self::notify(user.user_id, TFAResetMail::new(user.username))
+      .await;
 
-    Ok(Json(HttpMessage::new("Двуфакторная аутентификация была привязана к вашему профилю")))
+    Ok(Json(HttpMessage::new("Двуфакторная аутентификация была сброшена, все сессии пользователя завершены")))
   }
 
   /// Вход в аккаунт
   pub async fn login(
     db: &mut Database<Postgres>,
     redis: &mut Database<Redis>,
-    username: String,
+    ticket: String,
     code: String,
     user_agent: String
-  ) -> HttpResult<crate::models::Session> {
-    let user = TFAService::get_login_attempt(redis, db, username.clone())
-      .map_err(|_| HttpError::new("Запрос на авторизацию не найден (возможно, вы не успели)", Some(StatusCode::UNAUTHORIZED)))?;
+  ) -> HttpResult<TFALoginResponse> {
+    let not_found = || HttpError::new("Запрос на авторизацию не найден (возможно, вы не успели)", Some(StatusCode::UNAUTHORIZED));
+    let key = Self::generate_redis_2fa_key(&ticket);
 
-    let axum::Json((_, totp)) = TFAService::generate_2fa(user.username.clone(), user.totp_secret.clone())?;
+    // попытки считаем только по существующим тикетам,
+    // иначе любая случайная строка создавала бы в Redis счётчик
+    if !RedisService::exists(redis, &key)? {
+      return Err(not_found());
+    }
 
-    if !totp.check_current(&code)? {
-      return Err(HttpError::new("Неверный код", Some(StatusCode::UNAUTHORIZED)));
+    let attempts = RedisService::increment_temporarily(redis, &Self::generate_redis_attempts_key(&ticket), TICKET_LIFETIME)?;
+
+    if attempts > MAX_ATTEMPTS {
+      TFAService::remove_login_attempt(redis, &ticket)?;
+
+      return Err(HttpError::new("Слишком много неверных попыток, авторизуйтесь заново", Some(StatusCode::TOO_MANY_REQUESTS)));
     }
 
-    TFAService::remove_login_attempt(redis, username)?;
+    // тикет одноразовый, поэтому забираем его до проверки кода:
+    // резервный код сгорает при проверке и не должен сгореть, если тикет уже использован или истёк
+    let lifetime = RedisService::ttl_secs(redis, &key)?;
+    let record = RedisService::take::<String>(redis, &key)?
+      .ok_or_else(not_found)?;
+
+    let attempt = serde_json::from_str::<LoginTicket>(&record)?;
+
+    let (user, backup_codes_left) = match Self::check_login_attempt(db, redis, &attempt, &code, &user_agent) {
+      Ok(checked) => checked,
+      Err(err) => {
+        // код не подошёл - возвращаем тикет, чтобы можно было попробовать ещё раз
+        if lifetime > 0 {
+          RedisService::set_temporarily_secs(redis, &key, record, lifetime)?;
+        }
+
+        return Err(err);
+      }
+    };
+
+    RedisService::remove(redis, &Self::generate_redis_attempts_key(&ticket))?;
+
+    let axum::Json(session) = SessionService::create(db, redis, user, &user_agent, attempt.remember, attempt.device_id)
+      .await?;
+
+    Ok(Json(TFALoginResponse { session, backup_codes_left }))
+  }
+
+  /// Перешифровывает активным ключом TOTP секреты, которые хранятся
+  /// в открытом виде или зашифрованы старым ключом
+  ///
+  /// Возвращает количество обновлённых пользователей.
+  pub fn reencrypt_secrets(
+    db: &mut Database<Postgres>
+  ) -> NonJsonHttpResult<usize> {
+    let users = AuthRepository::find_with_stale_totp(db, CipherService::active_key_id())?;
+
+    for user in &users {
+      let secret = Self::get_secret(user)?;
+
+      AuthRepository::update_totp(db, user.id, CipherService::encrypt(&Self::secret_aad(user.id), &secret)?)?;
+    }
 
-    SessionService::get(db, (*user).clone(), &user_agent)
+    Ok(users.len())
   }
 
   // Вспомогательные функции
 
+  // привязывает шифротекст секрета к пользователю
+  fn secret_aad(
+    user_id: i32
+  ) -> Vec<u8> {
+    format!("users.totp_secret:{user_id}").into_bytes()
+  }
+
+  // расшифровывает TOTP секрет пользователя
+  fn get_secret(
+    user: &User
+  ) -> NonJsonHttpResult<String> {
+    match (&user.totp_secret, &user.totp_key_id) {
+      (Some(secret), Some(key_id)) => Ok(CipherService::decrypt(&Self::secret_aad(user.id), key_id, secret)?),
+      // секрет, который ещё не был зашифрован (см. reencrypt_secrets)
+      (Some(secret), None) => Ok(secret.clone()),
+      _ => Err(HttpError::new("К аккаунту не привязана двуфакторная аутентификация", Some(StatusCode::BAD_REQUEST)))
+    }
+  }
+
+  // проверяет TOTP код, а если он не подошёл - резервный код
+  // возвращает количество оставшихся резервных кодов, если был использован резервный код
+  pub fn verify_code(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    user: &User,
+    code: &str
+  ) -> NonJsonHttpResult<Option<usize>> {
+    let axum::Json((_, totp)) = TFAService::generate_2fa(user.username.clone(), Some(Self::get_secret(user)?))?;
+
+    if TOTPService::verify(redis, user.id, &totp, code)? {
+      return Ok(None);
+    }
+
+    Ok(Some(Self::use_backup_code(db, user, code)?))
+  }
+
+  // уведомляет пользователя на почту
+  // изменения уже применены, так что ошибку отправки только логируем
+  async fn notify<T>(
+    global_id: i32,
+    mail: T
+  )
+  where
+    T: TryInto<Email, Error = anyhow::Error>,
+  {
+    let result = match UserRepository::find(global_id).await {
+      Ok(user) => MailService::send(user.email, mail).await,
+      Err(e) => Err(e.into())
+    };
+
+    if let Err(e) = result {
+      log::error!("unable to send 2FA notification to user {global_id}: {e}");
+    }
+  }
+
+  // приводит резервный код к виду, в котором он хэшируется
+  fn normalize_backup_code(
+    code: &str
+  ) -> String {
+    code.chars()
+      .filter(|c| c.is_ascii_alphanumeric())
+      .map(|c| c.to_ascii_lowercase())
+      .collect()
+  }
+
+  // у резервных кодов мало энтропии, поэтому храним HMAC с серверным ключом:
+  // без ключа перебрать коды по дампу бд не получится
+  fn hash_backup_code(
+    key: &[u8],
+    code: &str
+  ) -> String {
+    format!("{BACKUP_CODE_HMAC_PREFIX}{}", HasherService::hmac_sha256(key, &Self::normalize_backup_code(code)))
+  }
+
+  // подходит ли код к сохранённому хэшу (сравнение за постоянное время)
+  fn matches_backup_code(
+    key: &[u8],
+    code: &str,
+    hash: &str
+  ) -> bool {
+    let code = Self::normalize_backup_code(code);
+
+    match hash.strip_prefix(BACKUP_CODE_HMAC_PREFIX) {
+      Some(mac) => HasherService::verify_hmac_sha256(key, &code, mac),
+      // коды, выданные до перехода на HMAC, работают до перевыпуска
+      None => HasherService::secure_eq(&HasherService::sha256(code), hash)
+    }
+  }
+
+  // генерирует новые резервные коды и сохраняет их хэши
+  fn generate_backup_codes(
+    db: &mut Database<Postgres>,
+    user_id: i32
+  ) -> NonJsonHttpResult<Vec<String>> {
+    let codes = (0..BACKUP_CODES_COUNT)
+      .map(|_| HasherService::generate_backup_code())
+      .collect::<Vec<String>>();
+
+    let key = SessionService::token_hash_key();
+
+    let hashes = codes.iter()
+      .map(|code| Self::hash_backup_code(key, code))
+      .collect::<Vec<String>>();
+
+    AuthRepository::update_backup_codes(db, user_id, serde_json::to_value(hashes)?)?;
+
+    Ok(codes)
+  }
+
+  // сжигает резервный код и возвращает сколько их осталось
+  fn use_backup_code(
+    db: &mut Database<Postgres>,
+    user: &User,
+    code: &str
+  ) -> NonJsonHttpResult<usize> {
+    let invalid = || HttpError::new("Неверный код", Some(StatusCode::UNAUTHORIZED));
+
+    let stored = user.backup_codes.clone()
+      .ok_or_else(invalid)?;
+
+    let mut hashes = serde_json::from_value::<Vec<String>>(stored.clone())?;
+    let key = SessionService::token_hash_key();
+
+    let position = hashes.iter()
+      .position(|hash| Self::matches_backup_code(key, code, hash))
+      .ok_or_else(invalid)?;
+
+    hashes.remove(position);
+
+    let left = hashes.len();
+
+    // если код одновременно пытаются использовать дважды, то пройдёт только один запрос
+    if !AuthRepository::replace_backup_codes(db, user.id, stored, serde_json::to_value(hashes)?)? {
+      return Err(invalid());
+    }
+
+    Ok(left)
+  }
+
   fn generate_redis_2fa_key(
-    key: String
+    ticket: &str
+  ) -> String {
+    format!("2fa:ticket:{}", ticket)
+  }
+
+  fn generate_redis_pending_key(
+    user_id: i32
+  ) -> String {
+    format!("2fa:pending:{}", user_id)
+  }
+
+  fn generate_redis_attempts_key(
+    ticket: &str
   ) -> String {
-    format!("2fa:{}", key)
+    format!("2fa:ticket:{}:attempts", ticket)
   }
 
-  // добавляет в редис
+  // добавляет в редис тикет для второго шага авторизации
   pub fn add_login_attempt(
     redis: &mut Database<Redis>,
-    username: String
-  ) -> HttpResult<HttpMessage> {
-    let redis_key = Self::generate_redis_2fa_key(username);
+    user_id: i32,
+    user_agent: &str,
+    remember: bool,
+    device_id: Option<String>
+  ) -> HttpResult<LoginTicketResponse> {
+    let ticket = HasherService::generate_code();
+    let attempt = serde_json::to_string(&LoginTicket {
+      user_id,
+      useragent: user_agent.to_owned(),
+      remember,
+      device_id
+    })?;
 
-    RedisService::set_temporarily(redis, &redis_key, 1, 5)?;
+    RedisService::set_temporarily(redis, &Self::generate_redis_2fa_key(&ticket), attempt, TICKET_LIFETIME)?;
 
-    Ok(Json(HttpMessage::new("Подтвердите вход с помощью TOTP кода (2FA).")))
+    Ok(Json(LoginTicketResponse {
+      message: String::from("Подтвердите вход с помощью TOTP кода (2FA)."),
+      ticket
+    }))
   }
 
-  fn get_login_attempt(
-    redis: &mut Database<Redis>,
+  // проверяет код по уже забранному тикету
+  // возвращает пользователя и количество оставшихся резервных кодов (если был использован резервный код)
+  fn check_login_attempt(
     db: &mut Database<Postgres>,
-    username: String
-  ) -> HttpResult<User> {
-    let redis_key = Self::generate_redis_2fa_key(username.clone());
+    redis: &mut Database<Redis>,
+    attempt: &LoginTicket,
+    code: &str,
+    user_agent: &str
+  ) -> NonJsonHttpResult<(User, Option<usize>)> {
+    // тикет привязан к клиенту, который прошёл проверку пароля
+    if attempt.useragent != user_agent {
+      return Err(HttpError::new("Запрос на авторизацию не найден (возможно, вы не успели)", Some(StatusCode::UNAUTHORIZED)));
+    }
 
-    RedisService::get::<String>(redis, &redis_key)?;
+    let user = AuthRepository::find(db, attempt.user_id)?;
+    let backup_codes_left = Self::verify_code(db, redis, &user, code)?;
 
-    Ok(Json(AuthRepository::find_by_username(db, &username)?))
+    Ok((user, backup_codes_left))
   }
 
   fn remove_login_attempt(
     redis: &mut Database<Redis>,
-    username: String
+    ticket: &str
   ) -> NonJsonHttpResult<()> {
-    let redis_key = Self::generate_redis_2fa_key(username);
+    RedisService::remove(redis, &Self::generate_redis_2fa_key(ticket))?;
 
-    Ok(RedisService::remove(redis, &redis_key)?)
+    Ok(RedisService::remove(redis, &Self::generate_redis_attempts_key(ticket))?)
   }
 
   fn generate_2fa(
@@ -124,16 +485,41 @@ impl TFAService {
   ) -> HttpResult<(String, TOTP)> {
     let secret = secret.unwrap_or(HasherService::generate_2fa_secret());
 
-    let totp = TOTP::new(
-      totp_rs::Algorithm::SHA1,
-      6,
-      1,
-      30,
-      secret.as_bytes().to_vec(),
-      Some(String::from("riverfallmc.ru")),
-      username
-    )?;
+    let totp = TOTPService::create(username, &secret)?;
 
     Ok(axum::Json((secret, totp)))
   }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const KEY: &[u8] = b"test-token-hash-key";
+
+  #[test]
+  fn hashes_backup_codes_with_server_key() {
+    let hash = TFAService::hash_backup_code(KEY, "abcde-12345");
+
+    assert!(hash.starts_with(BACKUP_CODE_HMAC_PREFIX));
+    assert_ne!(hash, TFAService::hash_backup_code(b"other-key", "abcde-12345"));
+    assert_ne!(&hash[BACKUP_CODE_HMAC_PREFIX.len()..], HasherService::sha256(String::from("abcde12345")));
+  }
+
+  #[test]
+  fn matches_backup_codes_in_any_format() {
+    let hash = TFAService::hash_backup_code(KEY, "abcde-12345");
+
+    assert!(TFAService::matches_backup_code(KEY, "abcde-12345", &hash));
+    assert!(TFAService::matches_backup_code(KEY, "ABCDE12345", &hash));
+    assert!(!TFAService::matches_backup_code(KEY, "abcde-12346", &hash));
+    assert!(!TFAService::matches_backup_code(b"other-key", "abcde-12345", &hash));
+  }
+
+  #[test]
+  fn matches_legacy_sha256_backup_codes() {
+    let legacy = HasherService::sha256(String::from("abcde12345"));
+
+    assert!(TFAService::matches_backup_code(KEY, "abcde-12345", &legacy));
+    assert!(!TFAService::matches_backup_code(KEY, "abcde-12346", &legacy));
+  }
+}
@@ -0,0 +1,626 @@
+use axum::{http::StatusCode, response::{IntoResponse, Response}, Json};
+use adjust::{database::{postgres::Postgres, redis::Redis, Database}, response::HttpError};
+use std::env;
+use serde::{Deserialize, Serialize};
+use crate::{misc::env_or, models::{Session, SessionCreate, User}, repository::{auth::AuthRepository, session::SessionRepository}, service::{auth::AuthService, hasher::HasherService, jwt::{Claims, JWTService, TokenKind}, redis::RedisService, revocation::RevocationService, session::SessionService, textures::TexturesService, uuid::UuidService, yggdrasil_key::YggdrasilKey}};
+use super::tfa::TFAService;
+
+lazy_static::lazy_static! {
+  static ref SERVER_NAME: String = env_or("YGGDRASIL_SERVER_NAME", String::from("RiverfallMC"));
+  static ref HOMEPAGE_URL: String = env_or("YGGDRASIL_HOMEPAGE_URL", String::from("https://riverfallmc.ru"));
+  static ref REGISTER_URL: Option<String> = env::var("YGGDRASIL_REGISTER_URL").ok();
+}
+
+// максимальная длина clientToken
+const MAX_CLIENT_TOKEN_LENGTH: usize = 128;
+// сколько ников можно запросить за раз в /api/profiles/minecraft
+const MAX_PROFILES_PER_LOOKUP: usize = 10;
+// сколько раз можно ввести неверный 2FA код через лаунчер
+const MAX_TFA_ATTEMPTS: u64 = 5;
+// за какое время считаются неверные 2FA коды (в минутах)
+const TFA_ATTEMPTS_WINDOW: u64 = 15;
+// сколько живёт запись о входе на сервер (в секундах)
+const JOIN_LIFETIME: u64 = 30;
+
+/// Ошибка в формате Yggdrasil
+///
+/// ```json
+/// { "error": "ForbiddenOperationException", "errorMessage": "Invalid token." }
+/// ```
+#[derive(Debug)]
+pub struct YggdrasilError {
+  status: StatusCode,
+  error: &'static str,
+  message: String
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct YggdrasilErrorBody {
+  error: &'static str,
+  error_message: String
+}
+
+pub type YggdrasilResult<T> = Result<Json<T>, YggdrasilError>;
+
+impl YggdrasilError {
+  pub fn forbidden(
+    message: &str
+  ) -> Self {
+    YggdrasilError {
+      status: StatusCode::FORBIDDEN,
+      error: "ForbiddenOperationException",
+      message: message.to_owned()
+    }
+  }
+
+  pub fn illegal_argument(
+    message: &str
+  ) -> Self {
+    YggdrasilError {
+      status: StatusCode::BAD_REQUEST,
+      error: "IllegalArgumentException",
+      message: message.to_owned()
+    }
+  }
+
+  pub fn invalid_token() -> Self {
+    Self::forbidden("Invalid token.")
+  }
+
+  pub fn invalid_credentials() -> Self {
+    Self::forbidden("Invalid credentials. Invalid username or password.")
+  }
+
+  pub fn internal<E>(
+    err: E
+  ) -> Self
+  where
+    E: std::fmt::Display
+  {
+    log::error!("yggdrasil request failed: {err}");
+
+    YggdrasilError {
+      status: StatusCode::INTERNAL_SERVER_ERROR,
+      error: "InternalServerError",
+      message: String::from("Internal server error.")
+    }
+  }
+}
+
+// ошибки наших сервисов переводим в формат Yggdrasil
+impl From<HttpError> for YggdrasilError {
+  fn from(err: HttpError) -> Self {
+    match err.1 {
+      Some(StatusCode::BAD_REQUEST) => Self::illegal_argument(&err.0.to_string()),
+      Some(status) if status.is_client_error() => Self::forbidden(&err.0.to_string()),
+      _ => Self::internal(err)
+    }
+  }
+}
+
+impl IntoResponse for YggdrasilError {
+  fn into_response(self) -> axum::response::Response {
+    (
+      self.status,
+      Json(YggdrasilErrorBody {
+        error: self.error,
+        error_message: self.message
+      })
+    ).into_response()
+  }
+}
+
+/// Игровой профиль (айди - UUID без дефисов)
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GameProfile {
+  pub id: String,
+  pub name: String,
+  /// Свойства профиля (отдаются только sessionserver)
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub properties: Option<Vec<YggdrasilProperty>>
+}
+
+/// Запись о входе игрока на сервер (между join и hasJoined)
+#[derive(Serialize, Deserialize)]
+struct JoinRecord {
+  profile: GameProfile,
+  ip: Option<String>
+}
+
+#[derive(Serialize)]
+pub struct YggdrasilUser {
+  pub id: String,
+  pub properties: Vec<YggdrasilProperty>
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct YggdrasilProperty {
+  pub name: String,
+  pub value: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub signature: Option<String>
+}
+
+/// Метаданные API (корень для authlib-injector)
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiMetadata {
+  pub meta: ApiMeta,
+  pub skin_domains: Vec<String>,
+  /// Публичный ключ, которым проверяются подписи свойств профиля
+  pub signature_publickey: String
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiMeta {
+  pub server_name: String,
+  pub implementation_name: &'static str,
+  pub implementation_version: &'static str,
+  pub links: ApiLinks,
+  /// Входим по нику, а не по почте
+  #[serde(rename = "feature.non_email_login")]
+  pub non_email_login: bool
+}
+
+#[derive(Serialize)]
+pub struct ApiLinks {
+  pub homepage: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub register: Option<String>
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticateResponse {
+  pub access_token: String,
+  pub client_token: String,
+  pub available_profiles: Vec<GameProfile>,
+  pub selected_profile: Option<GameProfile>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub user: Option<YggdrasilUser>
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RefreshResponse {
+  pub access_token: String,
+  pub client_token: String,
+  pub selected_profile: Option<GameProfile>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub user: Option<YggdrasilUser>
+}
+
+/// Yggdrasil authserver для лаунчеров (authlib-injector)
+///
+/// accessToken - это JWT типа ``launcher``, привязанный к сессии.
+/// Как и refresh токен, он одноразовый: после ``/refresh`` старый токен перестаёт действовать.
+pub struct YggdrasilService;
+
+impl YggdrasilService {
+  pub fn metadata() -> Json<ApiMetadata> {
+    Json(ApiMetadata {
+      meta: ApiMeta {
+        server_name: SERVER_NAME.clone(),
+        implementation_name: env!("CARGO_PKG_NAME"),
+        implementation_version: env!("CARGO_PKG_VERSION"),
+        links: ApiLinks {
+          homepage: HOMEPAGE_URL.clone(),
+          register: REGISTER_URL.clone()
+        },
+        non_email_login: true
+      },
+      skin_domains: TexturesService::source().domains(),
+      signature_publickey: YggdrasilKey::get().public_key_pem().to_owned()
+    })
+  }
+
+  pub fn authenticate(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    username: String,
+    password: String,
+    client_token: Option<String>,
+    request_user: bool,
+    user_agent: &str
+  ) -> YggdrasilResult<AuthenticateResponse> {
+    let user = Self::check_credentials(db, redis, &username, &password)?;
+
+    let client_token = match client_token.filter(|token| !token.is_empty()) {
+      Some(token) if token.len() > MAX_CLIENT_TOKEN_LENGTH => return Err(YggdrasilError::illegal_argument("Invalid client token.")),
+      Some(token) => token,
+      None => HasherService::generate_code()
+    };
+
+    // лаунчер остаётся авторизованным надолго, как с "запомнить меня"
+    let session = SessionService::open(db, redis, SessionCreate {
+      client_token: Some(client_token.clone()),
+      ..SessionService::build(&user, user_agent, true)
+    })?;
+
+    let (_, access_token) = Self::issue(db, redis, &session)?;
+    let profile = Self::profile(&user);
+
+    Ok(Json(AuthenticateResponse {
+      access_token,
+      client_token,
+      available_profiles: vec![profile.clone()],
+      selected_profile: Some(profile),
+      user: request_user.then(|| Self::user(&user))
+    }))
+  }
+
+  pub fn refresh(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    access_token: String,
+    client_token: Option<String>,
+    request_user: bool,
+    selected_profile: Option<GameProfile>
+  ) -> YggdrasilResult<RefreshResponse> {
+    let (session, claims) = Self::find_session(db, redis, &access_token, client_token.as_deref())?;
+    let generation = claims.gen.unwrap_or_default();
+
+    // повторное использование старого токена - как и с refresh токеном, завершаем сессию
+    if !SessionService::is_current_token(&session, generation, &access_token) {
+      SessionService::compromise(db, redis, &session)?;
+
+      return Err(YggdrasilError::invalid_token());
+    }
+
+    let user = AuthRepository::find(db, session.user_id)
+      .map_err(|_| YggdrasilError::invalid_token())?;
+    let profile = Self::profile(&user);
+
+    // у токена уже есть профиль, сменить его нельзя
+    if selected_profile.is_some_and(|selected| selected.id != profile.id) {
+      return Err(YggdrasilError::illegal_argument("Access token already has a profile assigned."));
+    }
+
+    // пока мы выдавали токен, этот же токен успели использовать ещё раз
+    let Some((session, access_token)) = Self::try_issue(db, redis, &session)? else {
+      SessionService::compromise(db, redis, &session)?;
+
+      return Err(YggdrasilError::invalid_token());
+    };
+
+    Ok(Json(RefreshResponse {
+      access_token,
+      client_token: session.client_token.unwrap_or_default(),
+      selected_profile: Some(profile),
+      user: request_user.then(|| Self::user(&user))
+    }))
+  }
+
+  pub fn validate(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    access_token: &str,
+    client_token: Option<&str>
+  ) -> Result<StatusCode, YggdrasilError> {
+    Self::find_current_session(db, redis, access_token, client_token)?;
+
+    Ok(StatusCode::NO_CONTENT)
+  }
+
+  // по спецификации всегда отвечает 204, даже если токен уже недействителен
+  pub fn invalidate(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    access_token: &str,
+    client_token: Option<&str>
+  ) -> Result<StatusCode, YggdrasilError> {
+    if let Ok(session) = Self::find_current_session(db, redis, access_token, client_token) {
+      SessionService::delete(db, redis, session.id)?;
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+  }
+
+  // завершает все сессии пользователя в лаунчерах
+  pub fn signout(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    username: String,
+    password: String
+  ) -> Result<StatusCode, YggdrasilError> {
+    let user = Self::check_credentials(db, redis, &username, &password)?;
+
+    let ids = SessionRepository::delete_launcher_sessions(db, user.id)
+      .map_err(YggdrasilError::internal)?;
+
+    RevocationService::revoke_sessions(redis, &ids)
+      .map_err(YggdrasilError::internal)?;
+
+    Ok(StatusCode::NO_CONTENT)
+  }
+
+  // клиент сообщает, что входит на сервер serverId
+  pub fn join(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    access_token: &str,
+    selected_profile: &str,
+    server_id: String,
+    ip: String
+  ) -> Result<StatusCode, YggdrasilError> {
+    let session = Self::find_current_session(db, redis, access_token, None)?;
+
+    let user = AuthRepository::find(db, session.user_id)
+      .map_err(|_| YggdrasilError::invalid_token())?;
+    let profile = Self::profile(&user);
+
+    if profile.id != selected_profile {
+      return Err(YggdrasilError::forbidden("Invalid profile."));
+    }
+
+    let record = serde_json::to_string(&JoinRecord { profile, ip: Some(ip) })
+      .map_err(YggdrasilError::internal)?;
+
+    RedisService::set_temporarily_secs(redis, &Self::generate_join_key(&server_id, &user.username), record, JOIN_LIFETIME)
+      .map_err(YggdrasilError::internal)?;
+
+    Ok(StatusCode::NO_CONTENT)
+  }
+
+  // сервер проверяет, что игрок действительно входил к нему
+  // если нет - отвечаем 204 без тела
+  pub fn has_joined(
+    redis: &mut Database<Redis>,
+    username: &str,
+    server_id: &str,
+    ip: Option<&str>
+  ) -> Result<Response, YggdrasilError> {
+    let key = Self::generate_join_key(server_id, username);
+
+    let record = RedisService::get::<Option<String>>(redis, &key)
+      .map_err(YggdrasilError::internal)?;
+
+    let Some(record) = record else {
+      return Ok(StatusCode::NO_CONTENT.into_response());
+    };
+
+    let record = serde_json::from_str::<JoinRecord>(&record)
+      .map_err(YggdrasilError::internal)?;
+
+    // если сервер передал ip, то он должен совпадать с ip клиента при join
+    // (запись при этом не трогаем, чтобы чужой запрос не сбросил вход игрока)
+    if ip.is_some_and(|ip| record.ip.as_deref() != Some(ip)) {
+      return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+
+    // запись одноразовая: если её успели забрать раньше нас, то входа нет
+    if RedisService::take::<String>(redis, &key).map_err(YggdrasilError::internal)?.is_none() {
+      return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+
+    // серверу всегда отдаём подписанные свойства
+    Ok(Json(Self::with_properties(record.profile, true)?).into_response())
+  }
+
+  // профиль по UUID (с дефисами или без)
+  // если профиля нет - отвечаем 204 без тела
+  pub fn profile_by_uuid(
+    db: &mut Database<Postgres>,
+    uuid: &str,
+    signed: bool
+  ) -> Result<Response, YggdrasilError> {
+    let user = UuidService::normalize(uuid)
+      .and_then(|uuid| AuthRepository::find_by_uuid(db, &uuid).ok());
+
+    let Some(user) = user else {
+      return Ok(StatusCode::NO_CONTENT.into_response());
+    };
+
+    Ok(Json(Self::with_properties(Self::profile(&user), signed)?).into_response())
+  }
+
+  // профиль по нику (без свойств)
+  // если профиля нет - отвечаем 204 без тела
+  pub fn profile_by_name(
+    db: &mut Database<Postgres>,
+    username: &String
+  ) -> Result<Response, YggdrasilError> {
+    let Ok(user) = AuthRepository::find_by_username(db, username) else {
+      return Ok(StatusCode::NO_CONTENT.into_response());
+    };
+
+    Ok(Json(Self::profile(&user)).into_response())
+  }
+
+  // профили по списку ников (без свойств), ненайденные ники пропускаются
+  pub fn profiles_by_names(
+    db: &mut Database<Postgres>,
+    mut usernames: Vec<String>
+  ) -> YggdrasilResult<Vec<GameProfile>> {
+    usernames.sort();
+    usernames.dedup();
+
+    if usernames.len() > MAX_PROFILES_PER_LOOKUP {
+      return Err(YggdrasilError::illegal_argument("Too many profiles requested."));
+    }
+
+    let profiles = AuthRepository::find_by_usernames(db, &usernames)
+      .map_err(YggdrasilError::internal)?
+      .iter()
+      .map(Self::profile)
+      .collect();
+
+    Ok(Json(profiles))
+  }
+
+  // добавляет к профилю свойство textures
+  fn with_properties(
+    profile: GameProfile,
+    signed: bool
+  ) -> Result<GameProfile, YggdrasilError> {
+    let textures = TexturesService::property(&profile.id, &profile.name, signed)
+      .map_err(YggdrasilError::internal)?;
+
+    Ok(GameProfile {
+      properties: Some(vec![YggdrasilProperty {
+        name: String::from("textures"),
+        value: textures.value,
+        signature: textures.signature
+      }]),
+      ..profile
+    })
+  }
+
+  fn generate_join_key(
+    server_id: &str,
+    username: &str
+  ) -> String {
+    format!("yggdrasil:join:{}:{}", server_id, username)
+  }
+
+  // ищет активную сессию лаунчера по текущему (не использованному) accessToken
+  pub fn find_current_session(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    access_token: &str,
+    client_token: Option<&str>
+  ) -> Result<Session, YggdrasilError> {
+    let (session, claims) = Self::find_session(db, redis, access_token, client_token)?;
+
+    if !SessionService::is_current_token(&session, claims.gen.unwrap_or_default(), access_token) {
+      return Err(YggdrasilError::invalid_token());
+    }
+
+    Ok(session)
+  }
+
+  // ищет активную сессию лаунчера по accessToken (в том числе уже использованному)
+  fn find_session(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    access_token: &str,
+    client_token: Option<&str>
+  ) -> Result<(Session, Claims), YggdrasilError> {
+    let claims = JWTService::decode(access_token, TokenKind::Launcher)
+      .map_err(|_| YggdrasilError::invalid_token())?
+      .0
+      .claims;
+
+    if RevocationService::is_revoked(redis, &claims).map_err(YggdrasilError::internal)? {
+      return Err(YggdrasilError::invalid_token());
+    }
+
+    let session = SessionRepository::find(db, claims.sid)
+      .map_err(|_| YggdrasilError::invalid_token())?;
+
+    if !SessionService::check_alive(db, redis, &session)? {
+      return Err(YggdrasilError::invalid_token());
+    }
+
+    if client_token.is_some_and(|token| session.client_token.as_deref() != Some(token)) {
+      return Err(YggdrasilError::invalid_token());
+    }
+
+    Ok((session, claims))
+  }
+
+  // выдаёт сессии новый accessToken (старый перестаёт действовать)
+  fn issue(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    session: &Session
+  ) -> Result<(Session, String), YggdrasilError> {
+    Self::try_issue(db, redis, session)?
+      .ok_or(YggdrasilError::invalid_token())
+  }
+
+  // возвращает None, если пока мы выдавали токен, сессию успели обновить или завершить
+  fn try_issue(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    session: &Session
+  ) -> Result<Option<(Session, String)>, YggdrasilError> {
+    let generation = session.refresh_generation + 1;
+
+    // сессия вот-вот истечёт - она уже завершена, токен не выдаём
+    let lifetime = SessionService::next_token_lifetime(db, redis, session)
+      .map_err(|err| match err.1 {
+        Some(StatusCode::UNAUTHORIZED) => YggdrasilError::invalid_token(),
+        _ => err.into()
+      })?;
+
+    let access_token = JWTService::generate_launcher(session, generation, lifetime)
+      .map_err(YggdrasilError::internal)?;
+
+    Ok(SessionService::store_token(db, session, generation, &access_token)?
+      .map(|session| (session, access_token)))
+  }
+
+  // проверяет ник и пароль
+  // лаунчеры не умеют 2FA, поэтому код дописывается к паролю: ``пароль:код``
+  fn check_credentials(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    username: &String,
+    password: &str
+  ) -> Result<User, YggdrasilError> {
+    if let Ok(user) = AuthService::check_credentials(db, username, password) {
+      if user.totp_secret.is_some() {
+        return Err(YggdrasilError::forbidden("Two-factor authentication is enabled. Enter your password as password:code."));
+      }
+
+      return Ok(user);
+    }
+
+    let (password, code) = password.rsplit_once(':')
+      .ok_or(YggdrasilError::invalid_credentials())?;
+
+    let user = AuthService::check_credentials(db, username, password)
+      .map_err(|_| YggdrasilError::invalid_credentials())?;
+
+    if user.totp_secret.is_none() {
+      return Err(YggdrasilError::invalid_credentials());
+    }
+
+    // пароль верный, поэтому ограничиваем подбор кодов (как и попытки по тикету в /2fa/login)
+    let attempts_key = Self::generate_tfa_attempts_key(user.id);
+
+    let attempts = RedisService::increment_temporarily(redis, &attempts_key, TFA_ATTEMPTS_WINDOW)
+      .map_err(YggdrasilError::internal)?;
+
+    if attempts > MAX_TFA_ATTEMPTS {
+      return Err(YggdrasilError::forbidden("Too many invalid two-factor codes. Try again later."));
+    }
+
+    if TFAService::verify_code(db, redis, &user, code.trim()).is_err() {
+      return Err(YggdrasilError::invalid_credentials());
+    }
+
+    RedisService::remove(redis, &attempts_key)
+      .map_err(YggdrasilError::internal)?;
+
+    Ok(user)
+  }
+
+  fn generate_tfa_attempts_key(
+    user_id: i32
+  ) -> String {
+    format!("yggdrasil:2fa_attempts:{}", user_id)
+  }
+
+  pub fn profile(
+    user: &User
+  ) -> GameProfile {
+    GameProfile {
+      id: user.uuid.clone(),
+      name: user.username.clone(),
+      properties: None
+    }
+  }
+
+  fn user(
+    user: &User
+  ) -> YggdrasilUser {
+    YggdrasilUser {
+      id: format!("{:032x}", user.user_id),
+      properties: Vec::new()
+    }
+  }
+}
@@ -1,2 +1,4 @@
 pub mod register;
-pub mod recovery;
\ No newline at end of file
+pub mod recovery;
+pub mod tfa_disabled;
+pub mod tfa_reset;
\ No newline at end of file
@@ -0,0 +1,29 @@
+use hashbrown::HashMap;
+use crate::service::mail::email::Email;
+
+pub struct TFADisabledMail {
+  username: String
+}
+
+impl TFADisabledMail {
+  pub fn new(
+    username: String
+  ) -> Self{
+    TFADisabledMail { username }
+  }
+}
+
+impl TryFrom<TFADisabledMail> for Email {
+  type Error = anyhow::Error;
+
+  fn try_from(value: TFADisabledMail) -> anyhow::Result<Self> {
+    let mut context = HashMap::new();
+    context.insert("username".to_string(), value.username);
+
+    Email::new(
+      "data/templates/tfa_disabled.html".to_string(),
+      "Двуфакторная аутентификация отключена".to_string(),
+      context
+    )
+  }
+}
\ No newline at end of file
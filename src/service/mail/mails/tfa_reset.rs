@@ -0,0 +1,29 @@
+use hashbrown::HashMap;
+use crate::service::mail::email::Email;
+
+pub struct TFAResetMail {
+  username: String
+}
+
+impl TFAResetMail {
+  pub fn new(
+    username: String
+  ) -> Self{
+    TFAResetMail { username }
+  }
+}
+
+impl TryFrom<TFAResetMail> for Email {
+  type Error = anyhow::Error;
+
+  fn try_from(value: TFAResetMail) -> anyhow::Result<Self> {
+    let mut context = HashMap::new();
+    context.insert("username".to_string(), value.username);
+
+    Email::new(
+      "data/templates/tfa_reset.html".to_string(),
+      "Двуфакторная аутентификация сброшена".to_string(),
+      context
+    )
+  }
+}
\ No newline at end of file
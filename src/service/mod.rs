@@ -3,9 +3,19 @@ pub mod logic;
 /// Остальные сервисы
 pub mod auth;
 pub mod authvalidate;
+pub mod cipher;
 pub mod hasher;
+pub mod introspection;
 pub mod jwt;
+pub mod keystore;
 pub mod redis;
+pub mod revocation;
 pub mod mail;
+pub mod owner_cache;
+pub mod password;
 pub mod session;
-pub mod time;
\ No newline at end of file
+pub mod textures;
+pub mod time;
+pub mod totp;
+pub mod uuid;
+pub mod yggdrasil_key;
\ No newline at end of file
@@ -0,0 +1,86 @@
+#![allow(dead_code)]
+
+use anyhow::Result;
+use adjust::database::{redis::Redis, Database};
+use crate::{misc::env_or, models::BaseUserInfo};
+use super::{jwt::Claims, redis::RedisService, time::TimeService};
+
+lazy_static::lazy_static! {
+  // сколько (максимум) живёт запись в кэше /owner, в секундах
+  static ref OWNER_CACHE_LIFETIME: u64 = env_or("OWNER_CACHE_LIFETIME", 300);
+}
+
+/// Кэш ответов ``/owner`` в Redis
+///
+/// Запись хранится по ``jti`` токена и живёт не дольше самого токена и его сессии.
+/// Попадание в кэш не продлевает сессию: ``last_activity`` обновляется
+/// при следующем промахе, то есть не реже раза в ``OWNER_CACHE_LIFETIME``.
+/// Отозванные токены отсекаются списком отзыва ещё до похода в кэш,
+/// а при смене пароля или ника кэш пользователя сбрасывается целиком.
+pub struct OwnerCacheService;
+
+impl OwnerCacheService {
+  fn generate_key(
+    jti: &str
+  ) -> String {
+    format!("auth:owner:{}", jti)
+  }
+
+  // множество ключей кэша пользователя (для сброса)
+  fn generate_user_key(
+    user_id: i32
+  ) -> String {
+    format!("auth:owner:user:{}", user_id)
+  }
+
+  pub fn get(
+    redis: &mut Database<Redis>,
+    claims: &Claims
+  ) -> Result<Option<BaseUserInfo>> {
+    let record = RedisService::get::<Option<String>>(redis, &Self::generate_key(&claims.jti))?;
+
+    // запись в старом формате считаем промахом
+    Ok(record.and_then(|record| serde_json::from_str::<BaseUserInfo>(&record).ok()))
+  }
+
+  // session_lifetime - сколько секунд (максимум) ещё проживёт сессия токена
+  pub fn put(
+    redis: &mut Database<Redis>,
+    claims: &Claims,
+    info: &BaseUserInfo,
+    session_lifetime: u64
+  ) -> Result<()> {
+    let now = TimeService::get_current_timestamp() as usize;
+
+    if claims.exp <= now {
+      return Ok(());
+    }
+
+    let lifetime = ((claims.exp - now) as u64)
+      .min(session_lifetime)
+      .min(*OWNER_CACHE_LIFETIME);
+
+    if lifetime == 0 {
+      return Ok(());
+    }
+
+    let key = Self::generate_key(&claims.jti);
+
+    RedisService::set_temporarily_secs(redis, &key, serde_json::to_string(info)?, lifetime)?;
+    RedisService::add_to_set(redis, &Self::generate_user_key(claims.user_id), &key, *OWNER_CACHE_LIFETIME)
+  }
+
+  // сбрасывает кэш пользователя (user_id - айди в сервисе user)
+  pub fn invalidate(
+    redis: &mut Database<Redis>,
+    user_id: i32
+  ) -> Result<()> {
+    let user_key = Self::generate_user_key(user_id);
+
+    for key in RedisService::get_set(redis, &user_key)? {
+      RedisService::remove(redis, &key)?;
+    }
+
+    RedisService::remove(redis, &user_key)
+  }
+}
@@ -0,0 +1,165 @@
+#![allow(dead_code)]
+
+use std::{env, sync::OnceLock};
+use anyhow::{anyhow, Context, Result};
+use argon2::{password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString}, Algorithm, Argon2, Params, Version};
+use super::hasher::HasherService;
+
+// параметры Argon2id, загружаются при запуске в PasswordService::init
+static ARGON2_PARAMS: OnceLock<Params> = OnceLock::new();
+
+/// Алгоритм, которым захэширован пароль в ``users.password``
+#[derive(Debug, PartialEq, Eq)]
+pub enum PasswordAlgorithm {
+  /// PHC-строка вида ``$argon2id$v=19$m=...,t=...,p=...$salt$hash``
+  Argon2id,
+  /// Устаревший формат: hex от ``sha256(password + users.salt)``
+  LegacySha256
+}
+
+pub struct PasswordService;
+
+impl PasswordService {
+  // читает и проверяет параметры Argon2id (по умолчанию - рекомендации OWASP)
+  pub fn init() -> Result<()> {
+    let params = Params::new(
+      Self::env_param("ARGON2_MEMORY_COST", 19 * 1024)?,
+      Self::env_param("ARGON2_TIME_COST", 2)?,
+      Self::env_param("ARGON2_PARALLELISM", 1)?,
+      None
+    ).map_err(|e| anyhow!("Invalid Argon2 parameters: {e}"))?;
+
+    // повторный вызов оставляет уже загруженные параметры
+    let _ = ARGON2_PARAMS.set(params);
+
+    Ok(())
+  }
+
+  fn env_param(
+    name: &str,
+    default: u32
+  ) -> Result<u32> {
+    match env::var(name) {
+      Ok(value) => value.trim().parse().with_context(|| format!("{name} must be a positive number")),
+      Err(_) => Ok(default)
+    }
+  }
+
+  fn params() -> &'static Params {
+    ARGON2_PARAMS.get()
+      .expect("PasswordService::init must be called at startup")
+  }
+
+  fn argon2() -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, Self::params().clone())
+  }
+
+  // определяет алгоритм по содержимому колонки
+  pub fn algorithm(
+    hash: &str
+  ) -> PasswordAlgorithm {
+    if hash.starts_with('$') {
+      PasswordAlgorithm::Argon2id
+    } else {
+      PasswordAlgorithm::LegacySha256
+    }
+  }
+
+  // хэширует пароль в PHC-строку (соль хранится внутри неё)
+  pub fn hash(
+    password: &str
+  ) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Self::argon2()
+      .hash_password(password.as_bytes(), &salt)
+      .map(|hash| hash.to_string())
+      .map_err(|_| anyhow!("Не получилось захэшировать пароль"))
+  }
+
+  // проверяет пароль
+  // salt используется только для устаревших SHA-256 хэшей
+  pub fn verify(
+    password: &str,
+    hash: &str,
+    salt: &str
+  ) -> bool {
+    match Self::algorithm(hash) {
+      PasswordAlgorithm::Argon2id => PasswordHash::new(hash)
+        .map(|parsed| Self::argon2().verify_password(password.as_bytes(), &parsed).is_ok())
+        .unwrap_or(false),
+      PasswordAlgorithm::LegacySha256 => HasherService::secure_eq(&HasherService::sha256(format!("{password}{salt}")), hash)
+    }
+  }
+
+  // нужно ли перехэшировать пароль
+  // (устаревший алгоритм или изменились параметры Argon2)
+  pub fn needs_rehash(
+    hash: &str
+  ) -> bool {
+    if Self::algorithm(hash) != PasswordAlgorithm::Argon2id {
+      return true;
+    }
+
+    let Ok(parsed) = PasswordHash::new(hash) else {
+      return true;
+    };
+
+    if parsed.algorithm != Algorithm::Argon2id.ident() {
+      return true;
+    }
+
+    let current = Self::params();
+
+    Params::try_from(&parsed)
+      .map(|params| params.m_cost() != current.m_cost()
+        || params.t_cost() != current.t_cost()
+        || params.p_cost() != current.p_cost())
+      .unwrap_or(true)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn hashes_and_verifies_argon2id() {
+    PasswordService::init().unwrap();
+
+    let hash = PasswordService::hash("hunter2").unwrap();
+
+    assert!(hash.starts_with("$argon2id$"));
+    assert_eq!(PasswordService::algorithm(&hash), PasswordAlgorithm::Argon2id);
+    assert!(PasswordService::verify("hunter2", &hash, ""));
+    assert!(!PasswordService::verify("hunter3", &hash, ""));
+    assert!(!PasswordService::needs_rehash(&hash));
+  }
+
+  #[test]
+  fn verifies_legacy_hash_and_asks_for_upgrade() {
+    PasswordService::init().unwrap();
+
+    let hash = HasherService::sha256(String::from("hunter2salt"));
+
+    assert_eq!(PasswordService::algorithm(&hash), PasswordAlgorithm::LegacySha256);
+    assert!(PasswordService::verify("hunter2", &hash, "salt"));
+    assert!(!PasswordService::verify("hunter2", &hash, "pepper"));
+    assert!(PasswordService::needs_rehash(&hash));
+  }
+
+  #[test]
+  fn asks_for_upgrade_when_params_change() {
+    PasswordService::init().unwrap();
+
+    let weaker = Params::new(8 * 1024, 1, 1, None).unwrap();
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, weaker)
+      .hash_password(b"hunter2", &salt)
+      .unwrap()
+      .to_string();
+
+    assert!(PasswordService::verify("hunter2", &hash, ""));
+    assert!(PasswordService::needs_rehash(&hash));
+  }
+}
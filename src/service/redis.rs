@@ -39,10 +39,120 @@ impl RedisService {
     Ok(redis.set_ex::<&str, V, ()>(id, value, mins*60)?)
   }
 
+  // достаёт значение и сразу удаляет его (атомарно)
+  pub fn take<T>(
+    redis: &mut Database<Redis>,
+    id: &str
+  ) -> Result<Option<T>>
+  where
+    T: redis::FromRedisValue,
+  {
+    Ok(redis.get_del::<&str, Option<T>>(id)?)
+  }
+
+  // увеличивает счётчик на 1
+  // при создании счётчика выставляет ему время жизни
+  pub fn increment_temporarily(
+    redis: &mut Database<Redis>,
+    id: &str,
+    mins: u64
+  ) -> Result<u64> {
+    let value = redis.incr::<&str, u64, u64>(id, 1)?;
+
+    if value == 1 {
+      redis.expire::<&str, ()>(id, (mins*60) as i64)?;
+    }
+
+    Ok(value)
+  }
+
   pub fn remove(
     redis: &mut Database<Redis>,
     id: &str
   ) -> Result<()> {
     Ok(redis.del::<&str, ()>(id)?)
   }
-}
\ No newline at end of file
+
+  // то же, что и set_temporarily, но время жизни в секундах
+  pub fn set_temporarily_secs<V>(
+    redis: &mut Database<Redis>,
+    id: &str,
+    value: V,
+    secs: u64
+  ) -> Result<()>
+  where
+    V: redis::ToRedisArgs,
+  {
+    Ok(redis.set_ex::<&str, V, ()>(id, value, secs)?)
+  }
+
+  // сколько секунд осталось жить записи (0, если записи нет или у неё нет срока жизни)
+  pub fn ttl_secs(
+    redis: &mut Database<Redis>,
+    id: &str
+  ) -> Result<u64> {
+    Ok(redis.ttl::<&str, i64>(id)?.max(0) as u64)
+  }
+
+  pub fn exists(
+    redis: &mut Database<Redis>,
+    id: &str
+  ) -> Result<bool> {
+    Ok(redis.exists::<&str, bool>(id)?)
+  }
+
+  // отправляет сообщение в pub/sub канал
+  pub fn publish(
+    redis: &mut Database<Redis>,
+    channel: &str,
+    message: &str
+  ) -> Result<()> {
+    Ok(redis.publish::<&str, &str, ()>(channel, message)?)
+  }
+
+  // добавляет значение в множество и продлевает время жизни множества (в секундах)
+  pub fn add_to_set(
+    redis: &mut Database<Redis>,
+    id: &str,
+    member: &str,
+    secs: u64
+  ) -> Result<()> {
+    redis.sadd::<&str, &str, ()>(id, member)?;
+
+    Ok(redis.expire::<&str, ()>(id, secs as i64)?)
+  }
+
+  // атомарно записывает число, только если оно больше уже записанного (или записи нет)
+  // возвращает false, если записанное число больше или равно value
+  pub fn set_if_greater(
+    redis: &mut Database<Redis>,
+    id: &str,
+    value: u64,
+    secs: u64
+  ) -> Result<bool> {
+    let script = redis::Script::new(r"
+      local current = redis.call('GET', KEYS[1])
+
+      if current and tonumber(current) >= tonumber(ARGV[1]) then
+        return 0
+      end
+
+      redis.call('SET', KEYS[1], ARGV[1], 'EX', ARGV[2])
+
+      return 1
+    ");
+
+    Ok(script
+      .key(id)
+      .arg(value)
+      .arg(secs)
+      .invoke::<i32>(&mut **redis)? == 1)
+  }
+
+  pub fn get_set(
+    redis: &mut Database<Redis>,
+    id: &str
+  ) -> Result<Vec<String>> {
+    Ok(redis.smembers::<&str, Vec<String>>(id)?)
+  }
+}
@@ -0,0 +1,110 @@
+use std::env;
+use anyhow::Result;
+use adjust::database::{redis::Redis, Database};
+use serde::{Deserialize, Serialize};
+use super::{jwt::{Claims, JWTService}, redis::RedisService, time::TimeService};
+
+lazy_static::lazy_static! {
+  // pub/sub канал, в который анонсируются отзывы токенов
+  static ref REVOCATION_CHANNEL: String = env::var("REVOCATION_CHANNEL")
+    .unwrap_or(String::from("auth:revocations"));
+}
+
+/// Сообщение об отзыве в канале ``REVOCATION_CHANNEL``
+///
+/// Отозван либо один токен (``jti``), либо все токены сессии (``sid``).
+/// После ``exp`` запись можно удалить из локального deny-list.
+#[derive(Serialize, Deserialize)]
+pub struct Revocation {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub sid: Option<i32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub jti: Option<String>,
+  pub exp: usize
+}
+
+/// Список отозванных токенов в Redis
+///
+/// Access и launcher токены самодостаточны, поэтому после завершения сессии
+/// они продолжают проходить проверку подписи до истечения.
+/// Запись в списке живёт столько, сколько ещё может жить отозванный токен.
+pub struct RevocationService;
+
+impl RevocationService {
+  fn generate_session_key(
+    session_id: i32
+  ) -> String {
+    format!("auth:revoked:sid:{}", session_id)
+  }
+
+  fn generate_token_key(
+    jti: &str
+  ) -> String {
+    format!("auth:revoked:jti:{}", jti)
+  }
+
+  // отзывает все токены сессии
+  pub fn revoke_session(
+    redis: &mut Database<Redis>,
+    session_id: i32
+  ) -> Result<()> {
+    // любой токен сессии (в том числе launcher токен) истечёт не позже, чем через этот срок
+    let lifetime = JWTService::max_lifetime() as u64;
+
+    RedisService::set_temporarily(redis, &Self::generate_session_key(session_id), 1, lifetime)?;
+
+    Self::announce(redis, Revocation {
+      sid: Some(session_id),
+      jti: None,
+      exp: TimeService::get_current_timestamp() as usize + lifetime as usize * 60
+    })
+  }
+
+  pub fn revoke_sessions(
+    redis: &mut Database<Redis>,
+    session_ids: &[i32]
+  ) -> Result<()> {
+    for session_id in session_ids {
+      Self::revoke_session(redis, *session_id)?;
+    }
+
+    Ok(())
+  }
+
+  // отзывает один токен
+  pub fn revoke_token(
+    redis: &mut Database<Redis>,
+    claims: &Claims
+  ) -> Result<()> {
+    let now = TimeService::get_current_timestamp() as usize;
+
+    // токен уже истёк сам
+    if claims.exp <= now {
+      return Ok(());
+    }
+
+    RedisService::set_temporarily_secs(redis, &Self::generate_token_key(&claims.jti), 1, (claims.exp - now) as u64)?;
+
+    Self::announce(redis, Revocation {
+      sid: None,
+      jti: Some(claims.jti.clone()),
+      exp: claims.exp
+    })
+  }
+
+  // отозван ли токен (сам по себе или вместе с сессией)
+  pub fn is_revoked(
+    redis: &mut Database<Redis>,
+    claims: &Claims
+  ) -> Result<bool> {
+    Ok(RedisService::exists(redis, &Self::generate_session_key(claims.sid))?
+      || RedisService::exists(redis, &Self::generate_token_key(&claims.jti))?)
+  }
+
+  fn announce(
+    redis: &mut Database<Redis>,
+    revocation: Revocation
+  ) -> Result<()> {
+    RedisService::publish(redis, &REVOCATION_CHANNEL, &serde_json::to_string(&revocation)?)
+  }
+}
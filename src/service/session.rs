@@ -1,105 +1,583 @@
 #![allow(dead_code)]
 
+use std::{env, sync::OnceLock};
+use anyhow::{bail, Context};
 use axum::{http::StatusCode, Json};
-use adjust::{database::{postgres::Postgres, Database}, response::{HttpError, HttpResult, NonJsonHttpResult}};
-use crate::{models::{Session, SessionCreate, User}, repository::session::SessionRepository, service::jwt::JWTService};
-use super::time::TimeService;
+use chrono::TimeDelta;
+use adjust::{database::{postgres::Postgres, redis::Redis, Database}, response::{HttpError, HttpMessage, HttpResult, NonJsonHttpResult}};
+use crate::{misc::env_or, models::{Session, SessionCreate, SessionRotate, SessionWithTokens, User}, repository::{auth::AuthRepository, session::SessionRepository, user::UserRepository}, service::jwt::{Claims, JWTService}};
+use super::{hasher::HasherService, redis::RedisService, revocation::RevocationService, time::TimeService};
+
+// ключ HMAC, которым хэшируются refresh токены перед сохранением в бд
+// загружается при запуске в SessionService::init
+static TOKEN_HASH_KEY: OnceLock<String> = OnceLock::new();
+
+lazy_static::lazy_static! {
+  // максимальный возраст сессии (в минутах), после него нужно войти заново
+  static ref SESSION_MAX_AGE: i64 = env_or("SESSION_MAX_AGE", 60 * 24 * 90);
+  // сколько сессия может простаивать (в минутах) без /refresh или /owner
+  static ref SESSION_IDLE_TIMEOUT: i64 = env_or("SESSION_IDLE_TIMEOUT", 60 * 24 * 14);
+  // сколько активных сессий может быть у пользователя (0 - без ограничений)
+  static ref MAX_SESSIONS_PER_USER: i64 = env_or("MAX_SESSIONS_PER_USER", 10);
+}
+
+// максимальная длина айди устройства
+const MAX_DEVICE_ID_LENGTH: usize = 128;
 
 pub struct SessionService;
 
 impl SessionService {
-  // создает запись сессии
-  pub fn create(
+  // загружает ключ ``TOKEN_HASH_KEY``
+  pub fn init() -> anyhow::Result<()> {
+    let key = env::var("TOKEN_HASH_KEY")
+      .context("The TOKEN_HASH_KEY environment variable was not found")?;
+
+    if key.trim().is_empty() {
+      bail!("TOKEN_HASH_KEY must not be empty");
+    }
+
+    // повторный вызов оставляет уже загруженный ключ
+    let _ = TOKEN_HASH_KEY.set(key);
+
+    Ok(())
+  }
+
+  // серверный ключ HMAC для секретов, которые хранятся в бд
+  // (refresh токены и резервные коды 2FA)
+  pub fn token_hash_key() -> &'static [u8] {
+    TOKEN_HASH_KEY.get()
+      .expect("SessionService::init must be called at startup")
+      .as_bytes()
+  }
+
+  // создает новую сессию при входе и выдаёт ей токены
+  //
+  // если клиент прислал айди устройства, то старая сессия этого устройства завершается,
+  // а если сессий больше MAX_SESSIONS_PER_USER, то завершаются самые старые
+  pub async fn create(
     db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
     user: User,
-    user_agent: &str
-  ) -> HttpResult<Session> {
-    let session = SessionCreate {
+    user_agent: &str,
+    remember: bool,
+    device_id: Option<String>
+  ) -> HttpResult<SessionWithTokens> {
+    let device_id = device_id
+      .map(|id| id.trim().to_owned())
+      .filter(|id| !id.is_empty());
+
+    if device_id.as_ref().is_some_and(|id| id.len() > MAX_DEVICE_ID_LENGTH) {
+      return Err(HttpError::new("Слишком длинный айди устройства", Some(StatusCode::BAD_REQUEST)));
+    }
+
+    let session = Self::open(db, redis, SessionCreate {
+      device_id,
+      ..Self::build(&user, user_agent, remember)
+    })?;
+
+    Self::issue(db, redis, session).await
+  }
+
+  // запись новой сессии (без токенов)
+  pub fn build(
+    user: &User,
+    user_agent: &str,
+    remember: bool
+  ) -> SessionCreate {
+    let now = TimeService::get_current_time();
+
+    SessionCreate {
       user_id: user.id,
       global_id: user.user_id,
       useragent: user_agent.to_owned(),
-      jwt: JWTService::generate(user.id)?,
-      refresh_token: JWTService::generate_refresh(user.id)?,
-      last_activity: TimeService::get_current_time(),
-    };
+      last_activity: now,
+      family_id: HasherService::generate_code(),
+      // хэш появится после выдачи первой пары токенов
+      refresh_token_hash: String::new(),
+      remember,
+      created_at: now,
+      device_id: None,
+      client_token: None,
+    }
+  }
+
+  // сохраняет новую сессию, предварительно завершив
+  // старые сессии того же устройства (или лаунчера) и самые старые сессии сверх MAX_SESSIONS_PER_USER
+  pub fn open(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    session: SessionCreate
+  ) -> NonJsonHttpResult<Session> {
+    if let Some(device_id) = &session.device_id {
+      let ids = SessionRepository::delete_by_device(db, session.user_id, device_id)?;
+      RevocationService::revoke_sessions(redis, &ids)?;
+    }
+
+    if let Some(client_token) = &session.client_token {
+      let ids = SessionRepository::delete_by_client_token(db, session.user_id, client_token)?;
+      RevocationService::revoke_sessions(redis, &ids)?;
+    }
+
+    if *MAX_SESSIONS_PER_USER > 0 {
+      // оставляем место под новую сессию
+      let ids = SessionRepository::find_oldest(db, session.user_id, *MAX_SESSIONS_PER_USER - 1)?;
 
-    Ok(Json(SessionRepository::add(db, session)?))
+      for id in ids {
+        Self::delete(db, redis, id)?;
+      }
+    }
+
+    Ok(SessionRepository::add(db, session)?)
   }
 
-  // обновляет jwt сессии
-  pub fn update(
+  // выдаёт сессии новую пару токенов
+  // (предыдущий refresh токен перестаёт действовать)
+  async fn issue(
     db: &mut Database<Postgres>,
-    session_id: i32,
-    jwt: &str
+    redis: &mut Database<Redis>,
+    session: Session
+  ) -> HttpResult<SessionWithTokens> {
+    Self::try_issue(db, redis, &session).await?
+      .map(Json)
+      .ok_or(HttpError::new("Сессия не была найдена", Some(StatusCode::UNAUTHORIZED)))
+  }
+
+  // возвращает None, если пока мы выдавали токены,
+  // сессию успели обновить или завершить
+  async fn try_issue(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    session: &Session
+  ) -> NonJsonHttpResult<Option<SessionWithTokens>> {
+    let generation = session.refresh_generation + 1;
+
+    // ранг кладём в токен, чтобы другим сервисам не нужно было ходить в сервис user
+    let rank = Self::get_rank(redis, session.global_id).await?;
+
+    let uuid = AuthRepository::find(db, session.user_id)?.uuid;
+
+    let jwt = JWTService::generate(session, rank)?;
+    let refresh_token = JWTService::generate_refresh(session, generation, Self::next_token_lifetime(db, redis, session)?)?;
+
+    let rotated = Self::store_token(db, session, generation, &refresh_token)?;
+
+    Ok(rotated.map(|session| SessionWithTokens { session, jwt, refresh_token, uuid }))
+  }
+
+  // ранг пользователя из сервиса user
+  //
+  // последний полученный ранг кэшируется в Redis, чтобы /refresh продолжал работать,
+  // пока сервис user недоступен (но не если пользователя там больше нет)
+  async fn get_rank(
+    redis: &mut Database<Redis>,
+    global_id: i32
+  ) -> NonJsonHttpResult<String> {
+    let not_found = || HttpError::new("Пользователь не был найден", Some(StatusCode::NOT_FOUND));
+    let key = Self::generate_rank_key(global_id);
+
+    match UserRepository::find(global_id).await {
+      Ok(user) => {
+        RedisService::set_temporarily(redis, &key, &user.rank, JWTService::max_lifetime() as u64)?;
+
+        Ok(user.rank)
+      },
+      Err(e) if e.downcast_ref::<reqwest::Error>().and_then(|e| e.status()) == Some(StatusCode::NOT_FOUND) => Err(not_found()),
+      Err(e) => {
+        log::warn!("unable to fetch rank of user {global_id}, using the cached one: {e}");
+
+        RedisService::get::<Option<String>>(redis, &key)?
+          .ok_or_else(not_found)
+      }
+    }
+  }
+
+  fn generate_rank_key(
+    global_id: i32
+  ) -> String {
+    format!("auth:rank:{}", global_id)
+  }
+
+  // запоминает хэш нового одноразового токена сессии (поколения generation)
+  // возвращает None, если сессию успели обновить или завершить
+  pub fn store_token(
+    db: &mut Database<Postgres>,
+    session: &Session,
+    generation: i32,
+    token: &str
+  ) -> NonJsonHttpResult<Option<Session>> {
+    Ok(SessionRepository::rotate(db, session.id, session.refresh_generation, SessionRotate {
+      refresh_token_hash: Self::hash_token(token),
+      refresh_generation: generation,
+      last_activity: TimeService::get_current_time()
+    })?)
+  }
+
+  // является ли токен текущим одноразовым токеном сессии
+  pub fn is_current_token(
+    session: &Session,
+    generation: i32,
+    token: &str
+  ) -> bool {
+    session.refresh_generation == generation
+      && HasherService::verify_hmac_sha256(Self::token_hash_key(), token, &session.refresh_token_hash)
+  }
+
+  // refresh токен не должен пережить максимальный возраст сессии
+  // None - до максимального возраста осталось меньше минуты
+  pub fn refresh_lifetime(
+    session: &Session
+  ) -> Option<usize> {
+    let left = (session.created_at + TimeDelta::minutes(*SESSION_MAX_AGE) - TimeService::get_current_time())
+      .num_minutes();
+
+    (left > 0).then(|| JWTService::refresh_lifetime(session.remember).min(left as usize))
+  }
+
+  // срок жизни нового одноразового токена сессии (в минутах)
+  // если сессия уже не успеет им воспользоваться, то завершаем её, а не выдаём истёкший токен
+  pub fn next_token_lifetime(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    session: &Session
+  ) -> NonJsonHttpResult<usize> {
+    match Self::refresh_lifetime(session) {
+      Some(minutes) => Ok(minutes),
+      None => {
+        Self::delete(db, redis, session.id)?;
+
+        Err(HttpError::new("Сессия истекла, войдите заново", Some(StatusCode::UNAUTHORIZED)))
+      }
+    }
+  }
+
+  // сколько секунд проживёт сессия, если ей только что пользовались
+  // (меньшее из SESSION_IDLE_TIMEOUT и остатка SESSION_MAX_AGE)
+  pub fn lifetime_after_touch(
+    session: &Session
+  ) -> u64 {
+    let max_age_left = (session.created_at + TimeDelta::minutes(*SESSION_MAX_AGE) - TimeService::get_current_time())
+      .num_seconds();
+
+    max_age_left
+      .min(*SESSION_IDLE_TIMEOUT * 60)
+      .max(0) as u64
+  }
+
+  // истекла ли сессия (по простою или по максимальному возрасту)
+  pub fn is_expired(
+    session: &Session
+  ) -> bool {
+    let now = TimeService::get_current_time();
+
+    now - session.last_activity > TimeDelta::minutes(*SESSION_IDLE_TIMEOUT)
+      || now - session.created_at > TimeDelta::minutes(*SESSION_MAX_AGE)
+  }
+
+  // проверяет, что сессия активна и не истекла
+  // истёкшую сессию сразу завершаем
+  pub fn check_alive(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    session: &Session
+  ) -> NonJsonHttpResult<bool> {
+    if !session.is_active {
+      return Ok(false);
+    }
+
+    if Self::is_expired(session) {
+      Self::delete(db, redis, session.id)?;
+
+      return Ok(false);
+    }
+
+    Ok(true)
+  }
+
+  // отмечает активность в сессии (продлевает её до SESSION_IDLE_TIMEOUT)
+  pub fn touch(
+    db: &mut Database<Postgres>,
+    session_id: i32
   ) -> NonJsonHttpResult<()> {
-    Ok(SessionRepository::update_jwt(db, session_id, jwt.to_string())?)
+    Ok(SessionRepository::touch(db, session_id, TimeService::get_current_time())?)
+  }
+
+  fn hash_token(
+    token: &str
+  ) -> String {
+    HasherService::hmac_sha256(Self::token_hash_key(), token)
+  }
+
+  // проверяет access токен без похода в бд:
+  // подпись, срок действия и список отозванных токенов
+  pub fn verify_access(
+    redis: &mut Database<Redis>,
+    jwt: &str
+  ) -> HttpResult<Claims> {
+    let claims = JWTService::decode_access(jwt)?
+      .0
+      .claims;
+
+    if RevocationService::is_revoked(redis, &claims)? {
+      return Err(HttpError::new("Токен был отозван", Some(StatusCode::UNAUTHORIZED)));
+    }
+
+    Ok(Json(claims))
   }
 
-  // ищет сессию по jwt
-  pub fn get_by_jwt(
+  // ищет сессию по уже проверенному access токену
+  pub fn get_by_claims(
     db: &mut Database<Postgres>,
-    jwt: String,
+    redis: &mut Database<Redis>,
+    claims: &Claims,
     check_active: bool
   ) -> HttpResult<Session> {
-    let session = SessionRepository::find_by_jwt(db, jwt.clone())?;
+    let not_found = || HttpError::new("Сессия не была найдена", Some(StatusCode::BAD_REQUEST));
+
+    let session = SessionRepository::find(db, claims.sid)
+      .map_err(|_| not_found())?;
 
-    if check_active && (!session.is_active || JWTService::is_active(jwt).is_err()) {
-      return Err(HttpError::new("Сессия не была найдена", Some(StatusCode::BAD_REQUEST)));
+    if check_active && !Self::check_alive(db, redis, &session)? {
+      return Err(not_found());
     }
 
     Ok(Json(session))
   }
 
-  // ищет сессию по refresh токену
-  pub fn get_by_refresh(
+  // выдаёт новую пару токенов по refresh токену
+  //
+  // каждый refresh токен можно использовать только один раз:
+  // если приходит уже использованный токен, то его (или текущий) украли,
+  // и мы завершаем всю сессию
+  //
+  // старый access токен (если клиент его прислал) и сам refresh токен отзываются,
+  // чтобы после ротации в сессии оставалась только новая пара
+  pub async fn rotate(
     db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
     refresh: String,
-    check_active: bool
-  ) -> HttpResult<Session> {
-    let session = SessionRepository::find_by_refresh(db, refresh.clone())?;
+    access: Option<String>
+  ) -> HttpResult<SessionWithTokens> {
+    let invalid = || HttpError::new("Сессия не была найдена", Some(StatusCode::UNAUTHORIZED));
+
+    let claims = JWTService::decode_refresh(&refresh)
+      .map_err(|_| invalid())?
+      .0
+      .claims;
+
+    let Some(generation) = claims.gen else {
+      return Err(invalid());
+    };
 
-    if check_active && (!session.is_active || JWTService::is_active(refresh).is_err()) {
-      return Err(HttpError::new("Сессия не была найдена", Some(StatusCode::BAD_REQUEST)));
+    let session = SessionRepository::find(db, claims.sid)
+      .map_err(|_| invalid())?;
+
+    if !Self::check_alive(db, redis, &session)? {
+      return Err(invalid());
     }
 
-    Ok(Json(session))
+    if !Self::is_current_token(&session, generation, &refresh) {
+      Self::compromise(db, redis, &session)?;
+
+      return Err(invalid());
+    }
+
+    // пока мы выдавали токены, этот же refresh токен успели использовать ещё раз
+    let Some(rotated) = Self::try_issue(db, redis, &session).await? else {
+      Self::compromise(db, redis, &session)?;
+
+      return Err(invalid());
+    };
+
+    RevocationService::revoke_token(redis, &claims)?;
+
+    // чужой или уже недействительный access токен просто игнорируем
+    let access = access.and_then(|access| JWTService::decode_access(&access).ok());
+
+    if let Some(access) = access.filter(|access| access.claims.sid == session.id) {
+      RevocationService::revoke_token(redis, &access.claims)?;
+    }
+
+    Ok(Json(rotated))
+  }
+
+  // завершает сессию, в которой повторно использовали refresh токен
+  pub fn compromise(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    session: &Session
+  ) -> NonJsonHttpResult<()> {
+    log::warn!("refresh token reuse detected in session {} (user {}), revoking it", session.id, session.user_id);
+
+    SessionRepository::mark_compromised(db, session.id)?;
+
+    Ok(RevocationService::revoke_session(redis, session.id)?)
   }
 
   pub fn delete(
     db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
     id: i32
   ) -> NonJsonHttpResult<()> {
-    Ok(SessionRepository::delete(db, id)?)
+    SessionRepository::delete(db, id)?;
+
+    Ok(RevocationService::revoke_session(redis, id)?)
   }
 
-  // ищет сессию по user_id и useragent
-  // и если её нет, то создает и возвращает её
-  pub fn get(
+  // завершает все сессии пользователя
+  pub fn delete_all(
     db: &mut Database<Postgres>,
-    user: User,
-    user_agent: &str,
-  ) -> HttpResult<Session> {
-    let session_result = SessionRepository::get(db, user.id, user_agent);
+    redis: &mut Database<Redis>,
+    user_id: i32
+  ) -> NonJsonHttpResult<()> {
+    let ids = SessionRepository::delete_all(db, user_id)?;
+
+    Ok(RevocationService::revoke_sessions(redis, &ids)?)
+  }
+
+  // завершает текущую сессию
+  //
+  // сессия отзывается целиком, но сам токен тоже попадает в deny-list по jti,
+  // чтобы сервисы с локальным списком отозванных токенов сразу его отбросили
+  pub fn logout(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    current: &Session,
+    claims: &Claims
+  ) -> HttpResult<HttpMessage> {
+    Self::delete(db, redis, current.id)?;
+    RevocationService::revoke_token(redis, claims)?;
+
+    Ok(Json(HttpMessage::new("Вы вышли из аккаунта")))
+  }
 
-    match session_result {
-      Ok(mut session) => {
-        if JWTService::is_active(session.refresh_token.clone()).is_err() {
-          Self::delete(db, session.id)?;
-          return Self::create(db, user, user_agent);
-        }
+  // завершает одну из сессий пользователя (например, с другого устройства)
+  pub fn revoke(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    current: &Session,
+    session_id: i32
+  ) -> HttpResult<HttpMessage> {
+    let session = SessionRepository::find(db, session_id)
+      .map_err(|_| HttpError::new("Сессия не была найдена", Some(StatusCode::NOT_FOUND)))?;
 
-        if JWTService::is_active(session.jwt.clone()).is_err() {
-          let new_jwt = JWTService::generate(session.user_id)?;
+    // чужие сессии не трогаем, и не палим что они существуют
+    if session.user_id != current.user_id || !session.is_active {
+      return Err(HttpError::new("Сессия не была найдена", Some(StatusCode::NOT_FOUND)));
+    }
 
-          SessionService::update(db, session.id, &new_jwt)?;
+    Self::delete(db, redis, session.id)?;
 
-          session.jwt = new_jwt;
-        }
+    Ok(Json(HttpMessage::new("Сессия была завершена")))
+  }
 
-        Ok(Json(session))
-      },
-      Err(diesel::NotFound) => Self::create(db, user, user_agent),
-      Err(e) => Err(e.into()),
+  // завершает все сессии пользователя, кроме текущей
+  pub fn revoke_others(
+    db: &mut Database<Postgres>,
+    redis: &mut Database<Redis>,
+    current: &Session
+  ) -> HttpResult<HttpMessage> {
+    let ids = SessionRepository::delete_others(db, current.user_id, current.id)?;
+
+    RevocationService::revoke_sessions(redis, &ids)?;
+
+    Ok(Json(HttpMessage::new("Все остальные сессии были завершены")))
+  }
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn session(
+    generation: i32,
+    token: &str,
+    created_minutes_ago: i64
+  ) -> Session {
+    TOKEN_HASH_KEY.get_or_init(|| String::from("test-token-hash-key"));
+
+    let now = TimeService::get_current_time();
+
+    Session {
+      id: 1,
+      user_id: 1,
+      global_id: 1,
+      useragent: String::from("test"),
+      is_active: true,
+      last_activity: now,
+      family_id: String::from("family"),
+      refresh_generation: generation,
+      compromised: false,
+      refresh_token_hash: SessionService::hash_token(token),
+      remember: false,
+      created_at: now - TimeDelta::minutes(created_minutes_ago),
+      device_id: None,
+      client_token: None
     }
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn accepts_only_current_token() {
+    let session = session(3, "third", 0);
+
+    assert!(SessionService::is_current_token(&session, 3, "third"));
+    assert!(!SessionService::is_current_token(&session, 2, "third"));
+    assert!(!SessionService::is_current_token(&session, 3, "second"));
+  }
+
+  #[test]
+  fn rotated_token_is_detected_as_reused() {
+    let before = session(1, "first", 0);
+    assert!(SessionService::is_current_token(&before, 1, "first"));
+
+    // после ротации в бд лежит хэш следующего токена
+    let after = session(2, "second", 0);
+
+    assert!(SessionService::is_current_token(&after, 2, "second"));
+    assert!(!SessionService::is_current_token(&after, 1, "first"));
+  }
+
+  #[test]
+  fn stores_hmac_instead_of_token() {
+    let session = session(1, "first", 0);
+
+    assert_ne!(session.refresh_token_hash, "first");
+    assert_eq!(session.refresh_token_hash, SessionService::hash_token("first"));
+  }
+
+  // истечение сессии: простой и максимальный возраст
+
+  #[test]
+  fn expires_after_idle_timeout() {
+    let mut idle = session(1, "first", 0);
+    idle.last_activity -= TimeDelta::minutes(*SESSION_IDLE_TIMEOUT + 1);
+
+    assert!(SessionService::is_expired(&idle));
+    assert!(!SessionService::is_expired(&session(1, "first", 0)));
+  }
+
+  #[test]
+  fn expires_after_max_age_even_if_active() {
+    let old = session(1, "first", *SESSION_MAX_AGE + 1);
+
+    assert!(SessionService::is_expired(&old));
+  }
+
+  #[test]
+  fn refresh_token_does_not_outlive_max_age() {
+    let fresh = session(1, "first", 0);
+    let lifetime = JWTService::refresh_lifetime(false);
+    assert!(SessionService::refresh_lifetime(&fresh)
+      .is_some_and(|minutes| minutes <= lifetime && minutes >= lifetime.min(*SESSION_MAX_AGE as usize - 1)));
+
+    let old = session(1, "first", *SESSION_MAX_AGE - 5);
+    assert!(SessionService::refresh_lifetime(&old).is_some_and(|minutes| minutes <= 5));
+  }
+
+  #[test]
+  fn issues_no_refresh_token_in_last_minute() {
+    let mut almost = session(1, "first", *SESSION_MAX_AGE);
+    almost.created_at += TimeDelta::seconds(30);
+
+    assert_eq!(SessionService::refresh_lifetime(&almost), None);
+    assert_eq!(SessionService::refresh_lifetime(&session(1, "first", *SESSION_MAX_AGE)), None);
+  }
+}
@@ -0,0 +1,219 @@
+#![allow(dead_code)]
+
+use std::env;
+use anyhow::Result;
+use data_encoding::BASE64;
+use reqwest::Url;
+use serde::Serialize;
+use super::{time::TimeService, yggdrasil_key::YggdrasilKey};
+
+lazy_static::lazy_static! {
+  // откуда берём скины и плащи
+  static ref SKIN_SOURCE: Box<dyn SkinSource> = Box::new(UrlSkinSource::from_env());
+}
+
+/// Текстура профиля (скин или плащ)
+#[derive(Serialize, Clone)]
+pub struct Texture {
+  pub url: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub metadata: Option<TextureMetadata>
+}
+
+#[derive(Serialize, Clone)]
+pub struct TextureMetadata {
+  /// ``slim`` для тонкой (Alex) модели
+  pub model: String
+}
+
+/// Текстуры профиля
+#[derive(Serialize, Default)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct Textures {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub skin: Option<Texture>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub cape: Option<Texture>
+}
+
+/// Источник скинов
+///
+/// Чтобы брать скины из другого места (например, из отдельного сервиса скинов),
+/// достаточно реализовать этот трейт и подставить реализацию в ``SKIN_SOURCE``.
+pub trait SkinSource: Send + Sync {
+  fn textures(
+    &self,
+    profile_id: &str,
+    username: &str
+  ) -> Result<Textures>;
+
+  // домены, с которых authlib-injector разрешит загружать текстуры
+  fn domains(&self) -> Vec<String>;
+}
+
+/// Скины по шаблонам ссылок ``YGGDRASIL_SKIN_URL`` и ``YGGDRASIL_CAPE_URL``
+///
+/// В шаблоне ``{uuid}`` заменяется на UUID профиля (без дефисов), а ``{name}`` - на ник.
+pub struct UrlSkinSource {
+  skin: Option<String>,
+  cape: Option<String>
+}
+
+impl UrlSkinSource {
+  pub fn from_env() -> Self {
+    UrlSkinSource {
+      skin: env::var("YGGDRASIL_SKIN_URL").ok(),
+      cape: env::var("YGGDRASIL_CAPE_URL").ok()
+    }
+  }
+
+  fn texture(
+    template: &Option<String>,
+    profile_id: &str,
+    username: &str
+  ) -> Option<Texture> {
+    template.as_ref().map(|template| Texture {
+      url: template
+        .replace("{uuid}", profile_id)
+        .replace("{name}", username),
+      metadata: None
+    })
+  }
+}
+
+impl SkinSource for UrlSkinSource {
+  fn textures(
+    &self,
+    profile_id: &str,
+    username: &str
+  ) -> Result<Textures> {
+    Ok(Textures {
+      skin: Self::texture(&self.skin, profile_id, username),
+      cape: Self::texture(&self.cape, profile_id, username)
+    })
+  }
+
+  fn domains(&self) -> Vec<String> {
+    let mut domains = [&self.skin, &self.cape]
+      .into_iter()
+      .flatten()
+      .filter_map(|template| Url::parse(template).ok())
+      .filter_map(|url| url.host_str().map(str::to_owned))
+      .collect::<Vec<String>>();
+
+    domains.dedup();
+
+    domains
+  }
+}
+
+/// Содержимое свойства ``textures`` (до base64)
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TexturesPayload {
+  timestamp: i64,
+  profile_id: String,
+  profile_name: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  signature_required: Option<bool>,
+  textures: Textures
+}
+
+/// Свойство ``textures`` профиля
+pub struct TexturesProperty {
+  /// base64 от JSON с текстурами
+  pub value: String,
+  /// SHA1withRSA подпись ``value`` ключом Yggdrasil
+  pub signature: Option<String>
+}
+
+pub struct TexturesService;
+
+impl TexturesService {
+  pub fn source() -> &'static dyn SkinSource {
+    SKIN_SOURCE.as_ref()
+  }
+
+  // собирает свойство textures, при signed - подписывает его
+  pub fn property(
+    profile_id: &str,
+    username: &str,
+    signed: bool
+  ) -> Result<TexturesProperty> {
+    let textures = Self::source().textures(profile_id, username)?;
+
+    Self::encode(profile_id, username, textures, signed.then(YggdrasilKey::get))
+  }
+
+  // кодирует текстуры в base64 и подписывает их ключом key
+  fn encode(
+    profile_id: &str,
+    username: &str,
+    textures: Textures,
+    key: Option<&YggdrasilKey>
+  ) -> Result<TexturesProperty> {
+    let payload = TexturesPayload {
+      timestamp: TimeService::get_current_timestamp() * 1000,
+      profile_id: profile_id.to_owned(),
+      profile_name: username.to_owned(),
+      signature_required: key.is_some().then_some(true),
+      textures
+    };
+
+    let value = BASE64.encode(serde_json::to_string(&payload)?.as_bytes());
+    let signature = key.map(|key| key.sign(value.as_bytes()));
+
+    Ok(TexturesProperty { value, signature })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use rsa::{pkcs1v15::{Signature, VerifyingKey}, pkcs8::DecodePublicKey, signature::Verifier, RsaPrivateKey, RsaPublicKey};
+  use sha1::Sha1;
+  use super::*;
+
+  const PROFILE_ID: &str = "b50ad385829d3141a2167e7d7539ba7f";
+
+  fn textures() -> Textures {
+    UrlSkinSource {
+      skin: Some(String::from("https://skins.example.com/{uuid}.png")),
+      cape: None
+    }.textures(PROFILE_ID, "Notch").unwrap()
+  }
+
+  fn decode(
+    property: &TexturesProperty
+  ) -> serde_json::Value {
+    serde_json::from_slice(&BASE64.decode(property.value.as_bytes()).unwrap()).unwrap()
+  }
+
+  #[test]
+  fn signs_textures_value() {
+    let private = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+    let key = YggdrasilKey::from_private(private).unwrap();
+
+    let property = TexturesService::encode(PROFILE_ID, "Notch", textures(), Some(&key)).unwrap();
+
+    let payload = decode(&property);
+    assert_eq!(payload["profileId"], PROFILE_ID);
+    assert_eq!(payload["profileName"], "Notch");
+    assert_eq!(payload["signatureRequired"], true);
+    assert_eq!(payload["textures"]["SKIN"]["url"], format!("https://skins.example.com/{PROFILE_ID}.png"));
+    assert!(payload["textures"].get("CAPE").is_none());
+
+    let public = RsaPublicKey::from_public_key_pem(key.public_key_pem()).unwrap();
+    let signature = BASE64.decode(property.signature.unwrap().as_bytes()).unwrap();
+    let signature = Signature::try_from(signature.as_slice()).unwrap();
+
+    assert!(VerifyingKey::<Sha1>::new(public).verify(property.value.as_bytes(), &signature).is_ok());
+  }
+
+  #[test]
+  fn leaves_unsigned_textures_without_signature() {
+    let property = TexturesService::encode(PROFILE_ID, "Notch", textures(), None).unwrap();
+
+    assert!(property.signature.is_none());
+    assert!(decode(&property).get("signatureRequired").is_none());
+  }
+}
@@ -0,0 +1,260 @@
+#![allow(dead_code)]
+
+use std::{env, str::FromStr, sync::OnceLock};
+use anyhow::{bail, Context, Result};
+use adjust::database::{redis::Redis, Database};
+use totp_rs::{Algorithm, TOTP};
+use super::{hasher::HasherService, redis::RedisService, time::TimeService};
+
+// настройки TOTP, загружаются при запуске в TOTPService::init
+static TOTP_CONFIG: OnceLock<TotpConfig> = OnceLock::new();
+
+/// Настройки TOTP деплоя
+///
+/// Если они не совпадают с настройками в приложении пользователя,
+/// то ни один код не пройдёт проверку, поэтому невалидные значения - ошибка запуска.
+struct TotpConfig {
+  algorithm: Algorithm,
+  digits: usize,
+  skew: u8,
+  step: u64,
+  issuer: String
+}
+
+impl TotpConfig {
+  fn from_env() -> Result<TotpConfig> {
+    let algorithm = match env::var("TOTP_ALGORITHM").as_deref() {
+      Err(_) | Ok("SHA1") => Algorithm::SHA1,
+      Ok("SHA256") => Algorithm::SHA256,
+      Ok("SHA512") => Algorithm::SHA512,
+      Ok(other) => bail!("TOTP_ALGORITHM must be SHA1, SHA256 or SHA512, got {other}")
+    };
+
+    let digits = Self::var("TOTP_DIGITS", 6)?;
+
+    if !(6..=8).contains(&digits) {
+      bail!("TOTP_DIGITS must be between 6 and 8, got {digits}");
+    }
+
+    let step = Self::var("TOTP_STEP", 30)?;
+
+    if step == 0 {
+      bail!("TOTP_STEP must be greater than 0");
+    }
+
+    Ok(TotpConfig {
+      algorithm,
+      digits,
+      skew: Self::var("TOTP_SKEW", 1)?,
+      step,
+      issuer: env::var("TOTP_ISSUER").unwrap_or(String::from("riverfallmc.ru"))
+    })
+  }
+
+  fn var<T: FromStr>(
+    name: &str,
+    default: T
+  ) -> Result<T> {
+    match env::var(name) {
+      Ok(value) => value.trim().parse().ok().with_context(|| format!("{name} must be a number, got {value}")),
+      Err(_) => Ok(default)
+    }
+  }
+}
+
+pub struct TOTPService;
+
+impl TOTPService {
+  // загружает и проверяет настройки ``TOTP_*``
+  pub fn init() -> Result<()> {
+    // повторный вызов оставляет уже загруженные настройки
+    let _ = TOTP_CONFIG.set(TotpConfig::from_env()?);
+
+    Ok(())
+  }
+
+  fn config() -> &'static TotpConfig {
+    TOTP_CONFIG.get()
+      .expect("TOTPService::init must be called at startup")
+  }
+
+  // создаёт TOTP с настройками деплоя
+  pub fn create(
+    username: String,
+    secret: &str
+  ) -> Result<TOTP> {
+    let config = Self::config();
+
+    Ok(TOTP::new(
+      config.algorithm,
+      config.digits,
+      config.skew,
+      config.step,
+      secret.as_bytes().to_vec(),
+      Some(config.issuer.clone()),
+      username
+    )?)
+  }
+
+  fn generate_redis_last_step_key(
+    user_id: i32
+  ) -> String {
+    format!("2fa:last_step:{}", user_id)
+  }
+
+  // ищет шаг (в пределах skew от времени time), которому соответствует код
+  // коды сравниваются за постоянное время, и проверяются все шаги окна
+  fn find_step(
+    totp: &TOTP,
+    code: &str,
+    time: u64
+  ) -> Option<u64> {
+    let current = time / totp.step;
+    let skew = totp.skew as u64;
+
+    (current.saturating_sub(skew)..=current + skew)
+      .filter(|step| HasherService::secure_eq(&totp.generate(step * totp.step), code))
+      .max()
+  }
+
+  // можно ли принять код шага step, если последним использовали шаг last
+  // (то же правило атомарно проверяет и записывает RedisService::set_if_greater)
+  fn is_fresh_step(
+    last: Option<u64>,
+    step: u64
+  ) -> bool {
+    last.is_none_or(|last| step > last)
+  }
+
+  // сколько (в секундах) нужно помнить последний шаг:
+  // пока код из окна skew ещё может пройти проверку
+  fn last_step_lifetime(
+    totp: &TOTP
+  ) -> u64 {
+    totp.step * (totp.skew as u64 * 2 + 1) + 60
+  }
+
+  // проверяет код и запоминает его шаг,
+  // чтобы один и тот же (или более старый) код нельзя было использовать повторно
+  pub fn verify(
+    redis: &mut Database<Redis>,
+    user_id: i32,
+    totp: &TOTP,
+    code: &str
+  ) -> Result<bool> {
+    let Some(step) = Self::find_step(totp, code, TimeService::get_current_timestamp() as u64) else {
+      return Ok(false);
+    };
+
+    // сравнение с последним шагом и запись - одной атомарной операцией,
+    // иначе два одновременных запроса с одним кодом пройдут оба
+    RedisService::set_if_greater(redis, &Self::generate_redis_last_step_key(user_id), step, Self::last_step_lifetime(totp))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use adjust::database::{redis::Redis, Pool};
+  use super::*;
+
+  // секрет и коды из RFC 6238 (SHA1)
+  const SECRET: &[u8] = b"12345678901234567890";
+
+  fn totp() -> TOTP {
+    TOTP::new(Algorithm::SHA1, 6, 1, 30, SECRET.to_vec(), None, String::from("test"))
+      .unwrap()
+  }
+
+  #[test]
+  fn finds_step_of_rfc_vector() {
+    // T = 59 -> 94287082
+    assert_eq!(TOTPService::find_step(&totp(), "287082", 59), Some(1));
+  }
+
+  #[test]
+  fn accepts_codes_within_skew() {
+    let totp = totp();
+    let code = totp.generate(59);
+
+    assert_eq!(TOTPService::find_step(&totp, &code, 89), Some(1));
+    assert_eq!(TOTPService::find_step(&totp, &code, 0), Some(1));
+  }
+
+  #[test]
+  fn rejects_codes_outside_skew() {
+    let totp = totp();
+    let code = totp.generate(59);
+
+    assert_eq!(TOTPService::find_step(&totp, &code, 59 + 30 * 2), None);
+  }
+
+  #[test]
+  fn rejects_wrong_codes() {
+    assert_eq!(TOTPService::find_step(&totp(), "000000", 59), None);
+    assert_eq!(TOTPService::find_step(&totp(), "", 59), None);
+  }
+
+  #[test]
+  fn accepts_only_steps_newer_than_last() {
+    assert!(TOTPService::is_fresh_step(None, 0));
+    assert!(TOTPService::is_fresh_step(Some(1), 2));
+    assert!(!TOTPService::is_fresh_step(Some(2), 2));
+    assert!(!TOTPService::is_fresh_step(Some(2), 1));
+  }
+
+  #[test]
+  fn rejects_replayed_and_older_codes_without_redis() {
+    let totp = totp();
+    let now = 30 * 1000 + 10;
+    let mut last = None;
+
+    let mut verify = |code: &str| match TOTPService::find_step(&totp, code, now) {
+      Some(step) if TOTPService::is_fresh_step(last, step) => {
+        last = Some(step);
+        true
+      },
+      _ => false
+    };
+
+    let current = totp.generate(now);
+    let previous = totp.generate(now - 30);
+    let next = totp.generate(now + 30);
+
+    assert!(verify(&previous));
+    assert!(verify(&current));
+    // тот же код повторно
+    assert!(!verify(&current));
+    // более старый код после нового
+    assert!(!verify(&previous));
+    assert!(verify(&next));
+  }
+
+  #[test]
+  fn remembers_last_step_for_whole_window() {
+    assert!(TOTPService::last_step_lifetime(&totp()) >= 30 * 3);
+  }
+
+  // нужен Redis: REDIS_URL=redis://... cargo test -- --ignored
+  #[test]
+  #[ignore]
+  fn rejects_replayed_and_older_codes() {
+    let pool = Pool::<Redis>::default();
+    let mut redis = pool.get().unwrap();
+    let totp = totp();
+    let user_id = -(std::process::id() as i32);
+    let now = TimeService::get_current_timestamp() as u64;
+
+    RedisService::remove(&mut redis, &TOTPService::generate_redis_last_step_key(user_id)).unwrap();
+
+    let current = totp.generate(now);
+    let previous = totp.generate(now - 30);
+
+    assert!(TOTPService::verify(&mut redis, user_id, &totp, &current).unwrap());
+    // тот же код повторно
+    assert!(!TOTPService::verify(&mut redis, user_id, &totp, &current).unwrap());
+    // более старый код после нового
+    assert!(!TOTPService::verify(&mut redis, user_id, &totp, &previous).unwrap());
+
+    RedisService::remove(&mut redis, &TOTPService::generate_redis_last_step_key(user_id)).unwrap();
+  }
+}
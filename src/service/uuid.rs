@@ -0,0 +1,142 @@
+use std::{env, sync::OnceLock};
+use anyhow::{bail, Result};
+use md5::{Digest, Md5};
+
+// как выдаём UUID новым аккаунтам, загружается при запуске в UuidService::init
+static MINECRAFT_UUID_MODE: OnceLock<UuidMode> = OnceLock::new();
+
+/// Какие UUID выдаются новым аккаунтам (``MINECRAFT_UUID_MODE``)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UuidMode {
+  Offline,
+  Random
+}
+
+impl UuidMode {
+  fn parse(
+    mode: &str
+  ) -> Result<UuidMode> {
+    match mode {
+      "offline" => Ok(UuidMode::Offline),
+      "random" => Ok(UuidMode::Random),
+      other => bail!("MINECRAFT_UUID_MODE must be offline or random, got {other}")
+    }
+  }
+}
+
+/// Minecraft UUID аккаунтов
+///
+/// UUID хранится в ``users.uuid`` (32 hex символа без дефисов) и не меняется вместе с ником.
+/// Новым аккаунтам он выдаётся по ``MINECRAFT_UUID_MODE``:
+/// * ``offline`` - v3 от ``OfflinePlayer:<ник>``, как на офлайн-серверах;
+/// * ``random`` - случайный v4.
+pub struct UuidService;
+
+impl UuidService {
+  // загружает ``MINECRAFT_UUID_MODE``
+  // UUID не меняются, поэтому опечатка в режиме - ошибка запуска, а не тихий ``offline``
+  pub fn init() -> Result<()> {
+    let mode = match env::var("MINECRAFT_UUID_MODE") {
+      Ok(mode) => UuidMode::parse(mode.trim())?,
+      Err(_) => UuidMode::Offline
+    };
+
+    // повторный вызов оставляет уже загруженный режим
+    let _ = MINECRAFT_UUID_MODE.set(mode);
+
+    Ok(())
+  }
+
+  // UUID для нового аккаунта
+  pub fn generate(
+    username: &str
+  ) -> String {
+    let mode = MINECRAFT_UUID_MODE.get()
+      .expect("UuidService::init must be called at startup");
+
+    match mode {
+      UuidMode::Offline => Self::offline(username),
+      UuidMode::Random => Self::random()
+    }
+  }
+
+  // UUID офлайн-игрока, как его считает сервер Minecraft:
+  // UUID v3 от ``OfflinePlayer:<ник>``
+  pub fn offline(
+    username: &str
+  ) -> String {
+    let hash: [u8; 16] = Md5::digest(format!("OfflinePlayer:{username}")).into();
+
+    Self::format(hash, 3)
+  }
+
+  // случайный UUID v4
+  pub fn random() -> String {
+    Self::format(rand::random::<[u8; 16]>(), 4)
+  }
+
+  // приводит UUID (с дефисами или без) к виду, в котором он хранится
+  pub fn normalize(
+    uuid: &str
+  ) -> Option<String> {
+    let uuid = uuid.replace('-', "").to_lowercase();
+
+    (uuid.len() == 32 && uuid.chars().all(|c| c.is_ascii_hexdigit()))
+      .then_some(uuid)
+  }
+
+  // выставляет версию и вариант (RFC 4122)
+  fn format(
+    mut bytes: [u8; 16],
+    version: u8
+  ) -> String {
+    bytes[6] = (bytes[6] & 0x0f) | (version << 4);
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    hex::encode(bytes)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn offline_uuid_matches_minecraft() {
+    // UUID.nameUUIDFromBytes("OfflinePlayer:Notch")
+    assert_eq!(UuidService::offline("Notch"), "b50ad385829d3141a2167e7d7539ba7f");
+  }
+
+  #[test]
+  fn offline_uuid_depends_on_case() {
+    assert_ne!(UuidService::offline("Notch"), UuidService::offline("notch"));
+  }
+
+  #[test]
+  fn random_uuid_is_v4() {
+    let uuid = UuidService::random();
+
+    assert_eq!(uuid.len(), 32);
+    assert_eq!(&uuid[12..13], "4");
+    assert!(matches!(&uuid[16..17], "8" | "9" | "a" | "b"));
+    assert_ne!(uuid, UuidService::random());
+  }
+
+  #[test]
+  fn parses_only_known_modes() {
+    assert_eq!(UuidMode::parse("offline").unwrap(), UuidMode::Offline);
+    assert_eq!(UuidMode::parse("random").unwrap(), UuidMode::Random);
+    assert!(UuidMode::parse("Offline").is_err());
+    assert!(UuidMode::parse("ofline").is_err());
+  }
+
+  #[test]
+  fn normalizes_dashed_uuids() {
+    assert_eq!(
+      UuidService::normalize("B50AD385-829D-3141-A216-7E7D7539BA7F").as_deref(),
+      Some("b50ad385829d3141a2167e7d7539ba7f")
+    );
+    assert_eq!(UuidService::normalize("b50ad385829d3141a2167e7d7539ba7"), None);
+    assert_eq!(UuidService::normalize("z50ad385829d3141a2167e7d7539ba7f"), None);
+  }
+}
@@ -0,0 +1,88 @@
+#![allow(dead_code)]
+
+use std::{env, fs};
+use anyhow::{Context, Result};
+use data_encoding::BASE64;
+use rsa::{pkcs1::DecodeRsaPrivateKey, pkcs1v15::SigningKey, pkcs8::{DecodePrivateKey, EncodePublicKey, LineEnding}, signature::{SignatureEncoding, Signer}, RsaPrivateKey, RsaPublicKey};
+use sha1::Sha1;
+
+lazy_static::lazy_static! {
+  static ref KEY: YggdrasilKey = YggdrasilKey::load()
+    .expect("Unable to load Yggdrasil signing key!");
+}
+
+/// Ключ Yggdrasil
+///
+/// Им подписываются свойства профилей (SHA1withRSA), а публичный ключ
+/// отдаётся в метаданных API, чтобы authlib-injector мог проверить подпись.
+/// Приватный ключ (RSA, PKCS#8/PKCS#1) лежит в ``YGGDRASIL_KEY_PATH``.
+pub struct YggdrasilKey {
+  signing: SigningKey<Sha1>,
+  public_pem: String
+}
+
+impl YggdrasilKey {
+  pub fn get() -> &'static YggdrasilKey {
+    &KEY
+  }
+
+  fn load() -> Result<YggdrasilKey> {
+    let path = env::var("YGGDRASIL_KEY_PATH")
+      .unwrap_or(String::from("data/yggdrasil.pem"));
+
+    let pem = fs::read_to_string(&path)
+      .with_context(|| format!("Unable to read {path}"))?;
+
+    let private = RsaPrivateKey::from_pkcs8_pem(&pem)
+      .or_else(|_| RsaPrivateKey::from_pkcs1_pem(&pem))
+      .with_context(|| format!("{path} is not an RSA private key"))?;
+
+    Self::from_private(private)
+  }
+
+  pub fn from_private(
+    private: RsaPrivateKey
+  ) -> Result<YggdrasilKey> {
+    let public_pem = RsaPublicKey::from(&private)
+      .to_public_key_pem(LineEnding::LF)?;
+
+    Ok(YggdrasilKey {
+      signing: SigningKey::<Sha1>::new(private),
+      public_pem
+    })
+  }
+
+  // подпись SHA1withRSA в base64
+  pub fn sign(
+    &self,
+    data: &[u8]
+  ) -> String {
+    BASE64.encode(&self.signing.sign(data).to_bytes())
+  }
+
+  // публичный ключ в PEM (SPKI)
+  pub fn public_key_pem(&self) -> &str {
+    &self.public_pem
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use rsa::{pkcs1v15::{Signature, VerifyingKey}, pkcs8::DecodePublicKey, signature::Verifier};
+  use super::*;
+
+  #[test]
+  fn signs_with_sha1_with_rsa() {
+    let private = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+    let key = YggdrasilKey::from_private(private).unwrap();
+
+    let public = RsaPublicKey::from_public_key_pem(key.public_key_pem()).unwrap();
+    let verifying = VerifyingKey::<Sha1>::new(public);
+
+    let signature = BASE64.decode(key.sign(b"value").as_bytes()).unwrap();
+    let signature = Signature::try_from(signature.as_slice()).unwrap();
+
+    assert!(verifying.verify(b"value", &signature).is_ok());
+    assert!(verifying.verify(b"other", &signature).is_err());
+  }
+}